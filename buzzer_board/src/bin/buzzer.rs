@@ -5,7 +5,9 @@
 use buzzer_board::button_task::debounced_button_presses;
 use buzzer_board::leds::led_task;
 use buzzer_board::net::{init_net_stack, net_task, tcp_task};
-use buzzer_board::{create_net_peripherals, gen_random_seed, ButtonChannel, LedOutputs, NUM_LEDS};
+use buzzer_board::{
+    create_net_peripherals, gen_random_seed, ButtonChannel, ButtonEvent, LedOutputs, NUM_LEDS,
+};
 use defmt::*;
 use embassy_executor::Spawner;
 use embassy_stm32::exti::Channel as _;
@@ -48,7 +50,7 @@ async fn main(spawner: Spawner) -> ! {
 
     let seed = gen_random_seed(p.RNG);
 
-    let button_channel = Channel::<NoopRawMutex, (u8, u64), 64>::new();
+    let button_channel = Channel::<NoopRawMutex, ButtonEvent, 64>::new();
     let button_channel: &'static ButtonChannel = make_static!(button_channel);
 
     // Configure LED pins
@@ -78,7 +80,7 @@ async fn main(spawner: Spawner) -> ! {
     unwrap!(spawner.spawn(net_task(&stack)));
 
     // Launch TCP connection task.
-    unwrap!(spawner.spawn(tcp_task(&stack, button_channel)));
+    unwrap!(spawner.spawn(tcp_task(&stack, button_channel, (seed >> 32) as u32)));
 
     // Launch button press task.
     unwrap!(spawner.spawn(debounced_button_presses(
@@ -1,126 +1,175 @@
-use defmt::info;
+use core::sync::atomic::Ordering;
+
+use common::arming::Verdict;
+use common::buttons::{self, ButtonConfig};
+use common::debounce::{Bounce, Debounce, Edge};
+use common::LedFrame;
+use defmt::{debug, info};
+use embassy_futures::select::{select3, select_array, Either3};
 use embassy_stm32::{
-    exti::{self, ExtiInput},
-    gpio::{self, Input, Pull},
+    exti::{AnyChannel, ExtiInput},
+    gpio::{AnyPin, Input, Pull},
 };
-use embassy_time::Instant;
-use futures::{future::FutureExt, select_biased};
-
-use crate::ButtonChannel;
+use embassy_time::{Instant, Timer};
 
-/// Total number of buzzer buttons.
-const NUM_BUTTONS: usize = 7;
+use crate::{
+    queue_led_command, ButtonChannel, ButtonEvent, LedCommand, ARMING, BUTTON_CONFIG,
+    BUTTON_CONFIG_CHANGED, DEBOUNCE_REJECTED, NUM_BUTTONS, NUM_LEDS,
+};
 
-/// Minimum time in milliseconds to pass between two flanks to be considered a new press.
-const MIN_DEBOUNCE_MILLIS: u64 = 100;
+/// Apply the lockout to a debounced edge and pass it on to the network task.
+async fn report(button_channel: &ButtonChannel, button_id: u8, edge: Edge, time_us: u64) {
+    let (verdict, won) = ARMING.lock(|arming| {
+        let mut arming = arming.borrow_mut();
+        let verdict = match edge {
+            Edge::Pressed => arming.on_press(button_id),
+            Edge::Released { .. } => arming.on_release(button_id),
+        };
+        let first = matches!(
+            verdict,
+            Verdict::Forward {
+                first_after_arm: true,
+                ..
+            }
+        );
+        (verdict, first && arming.first_press_wins())
+    });
 
-macro_rules! unpack_buttons {
-    ($buttons:expr; $($button:ident),+) => {
-        let [
-            $(
-                mut $button,
-            )+
-        ] = $buttons;
+    let Verdict::Forward {
+        locked,
+        first_after_arm,
+    } = verdict
+    else {
+        debug!("Discarding event of locked button {}", button_id);
+        return;
     };
-}
 
-macro_rules! fused_futures {
-    ($(($button:expr, $fut:pat)),+) => {
-        $(
-            let $fut= $button.wait_for_rising_edge().fuse();
-        )+
-    };
+    if won {
+        // Light the winner right away, the server learns about it with the press.
+        queue_led_command(LedCommand::Frame(LedFrame {
+            mask: LedFrame::all(NUM_LEDS as u8, false).mask,
+            on: 1 << button_id,
+        }));
+    }
+
+    button_channel
+        .send(ButtonEvent {
+            button_id,
+            time_us,
+            edge,
+            locked,
+            first_after_arm,
+        })
+        .await;
 }
 
-macro_rules! pinned_futures {
-    ($($fut:tt),+) => {
-        $(
-            futures::pin_mut!($fut);
-        )+
+fn exti_input<'d>(
+    pin: &'d mut AnyPin,
+    channel: &'d mut AnyChannel,
+    pull: buttons::Pull,
+) -> ExtiInput<'d, AnyPin> {
+    let pull = match pull {
+        buttons::Pull::None => Pull::None,
+        buttons::Pull::Up => Pull::Up,
+        buttons::Pull::Down => Pull::Down,
     };
+    ExtiInput::new(Input::new(pin, pull), channel)
 }
 
 #[embassy_executor::task]
 pub async fn debounced_button_presses(
-    buzzer_buttons: [(gpio::AnyPin, exti::AnyChannel); NUM_BUTTONS - 1],
-    board_button: (gpio::AnyPin, exti::AnyChannel),
+    buzzer_buttons: [(AnyPin, AnyChannel); NUM_BUTTONS - 1],
+    board_button: (AnyPin, AnyChannel),
     button_channel: &'static ButtonChannel,
 ) -> ! {
     info!("Launching button task");
 
-    let buttons: [_; NUM_BUTTONS - 1] = buzzer_buttons.map(|(button, exti_input)| {
-        let button = Input::new(button, Pull::Up);
-        ExtiInput::new(button, exti_input)
-    });
-    unpack_buttons!(buttons; b0, b1, b2, b3, b4, b5);
+    let [b0, b1, b2, b3, b4, b5] = buzzer_buttons;
+    let mut pins = [b0, b1, b2, b3, b4, b5, board_button];
 
-    // The board button works with Pull:Down and has to be set up individually.
-    let mut b6 = ExtiInput::new(Input::new(board_button.0, Pull::Down), board_button.1);
+    loop {
+        BUTTON_CONFIG_CHANGED.reset();
+        let config = BUTTON_CONFIG.lock(|config| *config.borrow());
+        info!("Applying button config: {:?}", config);
+        watch_buttons(&mut pins, &config, button_channel).await;
+    }
+}
+
+/// Report debounced edges of all buttons until the button config changed.
+async fn watch_buttons(
+    pins: &mut [(AnyPin, AnyChannel); NUM_BUTTONS],
+    config: &[ButtonConfig; NUM_BUTTONS],
+    button_channel: &ButtonChannel,
+) {
+    let [(p0, c0), (p1, c1), (p2, c2), (p3, c3), (p4, c4), (p5, c5), (p6, c6)] = pins;
+    let mut buttons = [
+        exti_input(p0, c0, config[0].pull),
+        exti_input(p1, c1, config[1].pull),
+        exti_input(p2, c2, config[2].pull),
+        exti_input(p3, c3, config[3].pull),
+        exti_input(p4, c4, config[4].pull),
+        exti_input(p5, c5, config[5].pull),
+        exti_input(p6, c6, config[6].pull),
+    ];
 
-    let mut last_pressed: [u64; NUM_BUTTONS] = [0; NUM_BUTTONS];
+    let now = Instant::now().as_micros();
+    let mut debounce: [Debounce; NUM_BUTTONS] =
+        core::array::from_fn(|idx| Debounce::new(config[idx], buttons[idx].is_high(), now));
 
     loop {
-        fused_futures!(
-            (b0, f0),
-            (b1, f1),
-            (b2, f2),
-            (b3, f3),
-            (b4, f4),
-            (b5, f5),
-            (b6, f6)
-        );
-        pinned_futures!(f0, f1, f2, f3, f4, f5, f6);
+        let deadline = debounce.iter().filter_map(Debounce::settle_deadline).min();
+        let settle_fut = async {
+            match deadline {
+                Some(deadline) => Timer::at(Instant::from_micros(deadline)).await,
+                None => core::future::pending().await,
+            }
+        };
 
-        select_biased! {
-            _ = f0 => {
-                let now = Instant::now().as_millis();
-                if now.saturating_sub(last_pressed[0]) > MIN_DEBOUNCE_MILLIS {
-                    button_channel.send((0, now)).await;
-                }
-                last_pressed[0] = now;
-            },
-            _ = f1 => {
-                let now = Instant::now().as_millis();
-                if now.saturating_sub(last_pressed[1]) > MIN_DEBOUNCE_MILLIS {
-                    button_channel.send((1, now)).await;
-                }
-                last_pressed[1] = now;
-            },
-            _ = f2 => {
-                let now = Instant::now().as_millis();
-                if now.saturating_sub(last_pressed[2]) > MIN_DEBOUNCE_MILLIS {
-                    button_channel.send((2, now)).await;
-                }
-                last_pressed[2] = now;
-            },
-            _ = f3 => {
-                let now = Instant::now().as_millis();
-                if now.saturating_sub(last_pressed[3]) > MIN_DEBOUNCE_MILLIS {
-                    button_channel.send((3, now)).await;
-                }
-                last_pressed[3] = now;
-            },
-            _ = f4 => {
-                let now = Instant::now().as_millis();
-                if now.saturating_sub(last_pressed[4]) > MIN_DEBOUNCE_MILLIS {
-                    button_channel.send((4, now)).await;
-                }
-                last_pressed[4] = now;
-            },
-            _ = f5 => {
-                let now = Instant::now().as_millis();
-                if now.saturating_sub(last_pressed[5]) > MIN_DEBOUNCE_MILLIS {
-                    button_channel.send((5, now)).await;
+        let flank = {
+            let [b0, b1, b2, b3, b4, b5, b6] = &mut buttons;
+            let flank_fut = select_array([
+                b0.wait_for_any_edge(),
+                b1.wait_for_any_edge(),
+                b2.wait_for_any_edge(),
+                b3.wait_for_any_edge(),
+                b4.wait_for_any_edge(),
+                b5.wait_for_any_edge(),
+                b6.wait_for_any_edge(),
+            ]);
+            match select3(flank_fut, settle_fut, BUTTON_CONFIG_CHANGED.wait()).await {
+                Either3::First(((), idx)) => Some(idx),
+                Either3::Second(()) => None,
+                Either3::Third(()) => return,
+            }
+        };
+
+        match flank {
+            Some(idx) => {
+                let now = Instant::now().as_micros();
+                let high = buttons[idx].is_high();
+                match debounce[idx].on_flank(high, now) {
+                    Ok(Some((edge, time_us))) => {
+                        report(button_channel, idx as u8, edge, time_us).await
+                    }
+                    Ok(None) => {}
+                    Err(Bounce) => {
+                        DEBOUNCE_REJECTED.fetch_add(1, Ordering::Relaxed);
+                    }
                 }
-                last_pressed[5] = now;
-            },
-            _ = f6 => {
-                let now = Instant::now().as_millis();
-                if now.saturating_sub(last_pressed[6]) > MIN_DEBOUNCE_MILLIS {
-                    button_channel.send((6, now)).await;
+            }
+            None => {
+                let now = Instant::now().as_micros();
+                for (idx, (button, state)) in buttons.iter().zip(debounce.iter_mut()).enumerate() {
+                    if state
+                        .settle_deadline()
+                        .is_some_and(|deadline| deadline <= now)
+                    {
+                        if let Some((edge, time_us)) = state.settle(button.is_high()) {
+                            report(button_channel, idx as u8, edge, time_us).await;
+                        }
+                    }
                 }
-                last_pressed[6] = now;
-            },
-        };
+            }
+        }
     }
 }
@@ -1,22 +1,77 @@
+use common::effects::{EffectKind, EffectLevel};
+use embassy_futures::select::select;
 use embassy_stm32::gpio::Level;
-use embassy_time::Timer;
+use embassy_time::{Duration, Instant, Timer};
 
-use crate::{LedOutputs, LED_CHANGE_Q, THROTTLE_TIME};
+use crate::{LedCommand, LedOutputs, LED_CHANGE_Q, LED_WAKE, NUM_LEDS, THROTTLE_TIME};
+
+/// Tick of the effect engine, also the resolution of the software PWM.
+const EFFECT_TICK: Duration = Duration::from_millis(1);
+
+/// Effect running on a single LED.
+#[derive(Clone, Copy)]
+struct RunningEffect {
+    kind: EffectKind,
+    started: Instant,
+    /// Position of the LED among all LEDs of the effect.
+    rank: u8,
+    /// Number of LEDs running the effect.
+    num: u8,
+}
 
 #[embassy_executor::task]
 pub async fn led_task(outputs: &'static mut LedOutputs) -> ! {
+    let mut effects: [Option<RunningEffect>; NUM_LEDS] = [None; NUM_LEDS];
+
     loop {
-        match LED_CHANGE_Q.dequeue() {
-            None => Timer::after(THROTTLE_TIME).await,
-            Some(led_update) => {
-                if let Some(led) = outputs.get_mut(led_update.button_id as usize) {
-                    if led_update.on {
-                        led.set_level(Level::High);
-                    } else {
-                        led.set_level(Level::Low);
+        while let Some(command) = LED_CHANGE_Q.dequeue() {
+            match command {
+                LedCommand::Frame(frame) => {
+                    // Apply all LEDs of a frame in one pass without yielding in between.
+                    for (idx, led) in outputs.iter_mut().enumerate() {
+                        if let Some(on) = frame.get(idx) {
+                            // Static levels replace running effects.
+                            effects[idx] = None;
+                            led.set_level(Level::from(on));
+                        }
+                    }
+                }
+                LedCommand::Effect(effect) => {
+                    let started = Instant::now();
+                    let leds = (0..NUM_LEDS).filter(|idx| effect.mask & (1 << idx) != 0);
+                    let num = leds.clone().count() as u8;
+                    for (rank, idx) in leds.enumerate() {
+                        effects[idx] = Some(RunningEffect {
+                            kind: effect.kind,
+                            started,
+                            rank: rank as u8,
+                            num,
+                        });
                     }
                 }
             }
         }
+
+        let now = Instant::now();
+        for (idx, led) in outputs.iter_mut().enumerate() {
+            let Some(effect) = effects[idx] else {
+                continue;
+            };
+            let elapsed_ms = (now - effect.started).as_millis() as u32;
+            match effect.kind.level(elapsed_ms, effect.rank, effect.num) {
+                EffectLevel::Running(on) => led.set_level(Level::from(on)),
+                EffectLevel::Done(on) => {
+                    led.set_level(Level::from(on));
+                    effects[idx] = None;
+                }
+            }
+        }
+
+        let tick = if effects.iter().any(Option::is_some) {
+            EFFECT_TICK
+        } else {
+            THROTTLE_TIME
+        };
+        select(Timer::after(tick), LED_WAKE.wait()).await;
     }
 }
@@ -1,17 +1,25 @@
 #![no_std]
 #![feature(type_alias_impl_trait)]
 
-use core::sync::atomic::AtomicU32;
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering};
 
-use common::LedUpdate;
+use common::arming::Arming;
+use common::buttons::{ButtonConfig, Pull};
+use common::debounce::Edge;
+use common::{
+    features, ConfigureButtons, FirmwareVersion, Hello, LedEffect, LedFrame, PROTOCOL_VERSION,
+};
 use embassy_stm32::gpio::{AnyPin, Output};
 use embassy_stm32::peripherals::{
     self, ETH, PA1, PA2, PA7, PB0, PB1, PC1, PC2, PC3, PC4, PC5, PE2, PG11, PG12, PG13, RNG,
 };
 use embassy_stm32::rng::Rng;
 use embassy_stm32::{bind_interrupts, eth, rng};
-use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex};
+use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
 use embassy_time::Duration;
 use heapless::mpmc::Q16;
 use heapless::Vec;
@@ -24,21 +32,152 @@ pub mod net;
 /// Initialized led output pins.
 pub type LedOutputs = Vec<Output<'static, AnyPin>, NUM_LEDS>;
 
-/// Channel of button presses.
-pub type ButtonChannel = Channel<NoopRawMutex, (u8, u64), 64>;
+/// Channel of debounced button events.
+pub type ButtonChannel = Channel<NoopRawMutex, ButtonEvent, 64>;
+
+/// Debounced change of a button which passed the lockout, see [`ARMING`].
+#[derive(Clone, Copy)]
+pub struct ButtonEvent {
+    pub button_id: u8,
+    /// Time in microseconds since boot.
+    pub time_us: u64,
+    pub edge: Edge,
+    pub locked: bool,
+    pub first_after_arm: bool,
+}
+
+/// Arming and lockout of the buttons, shared by the button and the network task.
+pub static ARMING: Mutex<CriticalSectionRawMutex, RefCell<Arming>> =
+    Mutex::new(RefCell::new(Arming::new()));
+
+/// Electrical configuration of the buttons, applied by the button task.
+pub static BUTTON_CONFIG: Mutex<CriticalSectionRawMutex, RefCell<[ButtonConfig; NUM_BUTTONS]>> =
+    Mutex::new(RefCell::new(DEFAULT_BUTTON_CONFIG));
+
+/// Wakes up the button task to apply a changed [`BUTTON_CONFIG`].
+pub static BUTTON_CONFIG_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// All buttons read high while pressed, the onboard button `B1` needs a pull-down.
+const DEFAULT_BUTTON_CONFIG: [ButtonConfig; NUM_BUTTONS] = {
+    let mut config = [ButtonConfig::active_high(Pull::Up); NUM_BUTTONS];
+    config[NUM_BUTTONS - 1] = ButtonConfig::active_high(Pull::Down);
+    config
+};
+
+/// Change the configuration of some buttons and return the configuration of all.
+pub fn configure_buttons(configure: ConfigureButtons) -> [ButtonConfig; NUM_BUTTONS] {
+    let config = BUTTON_CONFIG.lock(|config| {
+        let mut config = config.borrow_mut();
+        for (button_id, button) in config.iter_mut().enumerate() {
+            if configure.mask & (1 << button_id) != 0 {
+                *button = configure.config;
+            }
+        }
+        *config
+    });
+    BUTTON_CONFIG_CHANGED.signal(());
+    config
+}
 
 /// Queue of led changes.
-pub static LED_CHANGE_Q: Q16<LedUpdate> = Q16::new();
+pub static LED_CHANGE_Q: Q16<LedCommand> = Q16::new();
+
+/// Wakes up the led task when a change was queued.
+pub static LED_WAKE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Queue a change of the led outputs and apply it without waiting for the next tick.
+pub fn queue_led_command(command: LedCommand) {
+    if LED_CHANGE_Q.enqueue(command).is_err() {
+        LED_QUEUE_OVERFLOWS.fetch_add(1, Ordering::Relaxed);
+        defmt::warn!("LED change queue full, dropping command");
+    }
+    LED_WAKE.signal(());
+}
+
+/// Commands dropped because [`LED_CHANGE_Q`] was full.
+pub static LED_QUEUE_OVERFLOWS: AtomicU32 = AtomicU32::new(0);
+
+/// Flanks rejected by the debounce filter of the button task.
+pub static DEBOUNCE_REJECTED: AtomicU32 = AtomicU32::new(0);
+
+/// Change of the led outputs, single updates are enqueued as frames as well.
+pub enum LedCommand {
+    Frame(LedFrame),
+    Effect(LedEffect),
+}
 
 /// Busy-loop throttle time for tasks.
 pub const THROTTLE_TIME: Duration = Duration::from_millis(10);
 
-/// Game initialization time.
-pub static INIT_TIME: AtomicU32 = AtomicU32::new(0);
+/// Time to wait before connecting to the server again.
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Interval of status reports sent to the server while connected.
+pub const STATUS_INTERVAL: Duration = Duration::from_secs(5);
 
 /// Number of led pins.
 pub const NUM_LEDS: usize = 6;
 
+/// Total number of buzzer buttons, including the onboard user button.
+pub const NUM_BUTTONS: usize = 7;
+
+/// Firmware version taken from the crate version.
+pub const FIRMWARE_VERSION: FirmwareVersion = FirmwareVersion {
+    major: parse_u8(env!("CARGO_PKG_VERSION_MAJOR")),
+    minor: parse_u8(env!("CARGO_PKG_VERSION_MINOR")),
+    patch: parse_u8(env!("CARGO_PKG_VERSION_PATCH")),
+};
+
+/// Features this firmware supports, see [`common::features`].
+pub const FEATURES: u32 = features::LED_UPDATE
+    | features::LED_FRAME
+    | features::LED_EFFECTS
+    | features::BUTTON_RELEASE
+    | features::ARMING
+    | features::FIRST_PRESS_WINS
+    | features::BUTTON_CONFIG;
+
+/// Host part of the IP address set with `BOARD_IP_HOST` when building, derived from the board ID
+/// otherwise.
+pub const BOARD_IP_HOST: Option<u8> = match option_env!("BOARD_IP_HOST") {
+    Some(host) => Some(parse_u8(host)),
+    None => None,
+};
+
+const fn parse_u8(s: &str) -> u8 {
+    let bytes = s.as_bytes();
+    let mut value = 0;
+    let mut idx = 0;
+    while idx < bytes.len() {
+        value = value * 10 + (bytes[idx] - b'0');
+        idx += 1;
+    }
+    value
+}
+
+/// Board ID derived from the 96-bit unique device ID of the MCU.
+pub fn board_id() -> u32 {
+    embassy_stm32::uid::uid()
+        .chunks_exact(4)
+        .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .fold(0, |acc, word| acc ^ word)
+}
+
+/// Handshake message announcing this board to the server.
+///
+/// `boot_id` is random per boot and tells the server that press sequence numbers restarted.
+pub fn hello(boot_id: u32) -> Hello {
+    Hello {
+        protocol_version: PROTOCOL_VERSION,
+        firmware_version: FIRMWARE_VERSION,
+        board_id: board_id(),
+        num_buttons: NUM_BUTTONS as u8,
+        num_leds: NUM_LEDS as u8,
+        features: FEATURES,
+        boot_id,
+    }
+}
+
 bind_interrupts!(pub struct Irqs {
     ETH => eth::InterruptHandler;
     RNG => rng::InterruptHandler<peripherals::RNG>;
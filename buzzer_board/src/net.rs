@@ -1,9 +1,16 @@
+use crate::{
+    board_id, configure_buttons, hello, queue_led_command, ButtonChannel, ButtonEvent, Irqs,
+    LedCommand, NetPeripherals, ARMING, BOARD_IP_HOST, BUTTON_CONFIG, DEBOUNCE_REJECTED,
+    LED_QUEUE_OVERFLOWS, NUM_BUTTONS, RECONNECT_DELAY, STATUS_INTERVAL,
+};
+use common::debounce::Edge;
+use common::{
+    encode_frame, BoardStatus, BoardToServer, ButtonConfig, ButtonConfigReport, ButtonPress,
+    ButtonRelease, LinkTiming, MsgBuffer, Pong, ServerToBoard, MAX_FRAME_SIZE,
+};
 use core::sync::atomic::Ordering;
-
-use crate::{ButtonChannel, Irqs, NetPeripherals, INIT_TIME, LED_CHANGE_Q};
-use common::{ButtonPress, Message, MsgBuffer};
 use defmt::*;
-use embassy_futures::select::{select, Either};
+use embassy_futures::select::{select3, Either3};
 use embassy_net::tcp::TcpSocket;
 use embassy_net::{tcp::Error::ConnectionReset, Ipv4Address, Ipv4Cidr, Stack, StackResources};
 use embassy_stm32::eth::PacketQueue;
@@ -11,8 +18,7 @@ use embassy_stm32::eth::{generic_smi::GenericSMI, Ethernet};
 use embassy_stm32::peripherals::ETH;
 use embassy_time::{Duration, Instant, Timer};
 use embedded_io_async::Write;
-use heapless::Vec;
-use postcard::to_slice;
+use heapless::{Deque, Vec};
 use static_cell::StaticCell;
 
 pub type Device = Ethernet<'static, ETH, GenericSMI>;
@@ -23,7 +29,14 @@ pub async fn net_task(stack: &'static Stack<Device>) -> ! {
 }
 
 pub fn init_net_stack(net_p: NetPeripherals, seed: u64) -> &'static Stack<Device> {
-    let mac_addr = [0x00, 0x00, 0xDE, 0xAD, 0xBE, 0xEF];
+    // Several boards share one network, so both addresses are derived from the board ID.
+    let id = board_id().to_be_bytes();
+    let mac_addr = [0x02, 0x00, id[0], id[1], id[2], id[3]];
+    let host = board_host_addr(board_id());
+    info!(
+        "Using MAC address {:x} and IP 192.168.100.{}",
+        mac_addr, host
+    );
 
     static PACKETS: StaticCell<PacketQueue<16, 16>> = StaticCell::new();
 
@@ -51,7 +64,7 @@ pub fn init_net_stack(net_p: NetPeripherals, seed: u64) -> &'static Stack<Device
 
     // Set laptop IP to 192.168.100.1 and listen with `netcat -l 8000`
     let config = embassy_net::Config::ipv4_static(embassy_net::StaticConfigV4 {
-        address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 100, 5), 24),
+        address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 100, host), 24),
         dns_servers: Vec::new(),
         gateway: Some(Ipv4Address::new(192, 168, 100, 1)),
     });
@@ -66,53 +79,145 @@ pub fn init_net_stack(net_p: NetPeripherals, seed: u64) -> &'static Stack<Device
     ))
 }
 
+/// Host part of the static IP address of a board, in 10..250 to stay clear of the laptop.
+///
+/// Two boards get the same address about once in 240 pairs. The server rejects the second one,
+/// which then has to be flashed with a fixed address, e.g. `BOARD_IP_HOST=42 cargo run`.
+fn board_host_addr(board_id: u32) -> u8 {
+    match BOARD_IP_HOST {
+        Some(host) => host,
+        None => 10 + (board_id % 240) as u8,
+    }
+}
+
+/// Button events which were sent but not yet acknowledged by the server.
+type UnackedEvents = Deque<BoardToServer, 32>;
+
+/// Messages to send in response to the messages of one read.
+type Replies = Vec<BoardToServer, 16>;
+
 #[embassy_executor::task]
-pub async fn tcp_task(stack: &'static Stack<Device>, button_channel: &'static ButtonChannel) -> ! {
+pub async fn tcp_task(
+    stack: &'static Stack<Device>,
+    button_channel: &'static ButtonChannel,
+    boot_id: u32,
+) -> ! {
     let mut tx_buf = [0u8; 1024];
     let mut rx_buf = [0u8; 1024];
 
+    // Kept across reconnects, so that events racing a connection reset are retransmitted.
+    let mut unacked = UnackedEvents::new();
+    let mut next_seq: u32 = 1;
+    // Board clock in microseconds when the current game was initialized.
+    let mut game_start_us: Option<u64> = None;
+
+    // Counters for the status reports.
+    let mut connections: u32 = 0;
+    let mut presses_sent: u32 = 0;
+    let mut button_queue_peak: u8 = 0;
+
     let endpoint_ip = embassy_net::IpAddress::Ipv4(Ipv4Address([192, 168, 100, 1]));
     let endpoint = embassy_net::IpEndpoint::new(endpoint_ip, 8000);
 
+    let mut first_attempt = true;
     'outer: loop {
+        // Also keeps a board which the server rejects from flooding it with handshakes.
+        if !core::mem::take(&mut first_attempt) {
+            Timer::after(RECONNECT_DELAY).await;
+        }
         info!("Connecting TCP socket to {:?}", endpoint);
         let mut tcp_socket = TcpSocket::new(stack, &mut rx_buf, &mut tx_buf);
 
         if let Err(e) = tcp_socket.connect(endpoint).await {
             warn!("Failed to connect to endpoint {:?}: {}", &endpoint, e);
-            Timer::after(Duration::from_secs(1)).await;
             continue 'outer;
         }
 
+        let connected_at = Instant::now();
+        connections += 1;
         let (mut reader, mut writer) = tcp_socket.split();
 
         // Reader state
         let mut msg_buffer = MsgBuffer::<1024>::default();
-        let mut remaining_err_on_zero = 3;
 
         // Writer state
-        let mut serialize_buffer = [0u8; 128];
+        let mut serialize_buffer = [0u8; MAX_FRAME_SIZE];
+
+        // Announce ourselves before anything else is sent.
+        let hello = BoardToServer::Hello(hello(boot_id));
+        info!("Sending handshake: {:?}", hello);
+        let serialized = encode_frame(&hello, &mut serialize_buffer).unwrap();
+        if let Err(e) = writer.write_all(serialized).await {
+            warn!("Failed to send handshake: {:?}", e);
+            continue 'outer;
+        }
+
+        // Retransmit events which might have been lost with the previous connection.
+        for event in unacked.iter() {
+            info!("Retransmitting unacknowledged event: {:?}", event);
+            let serialized = encode_frame(event, &mut serialize_buffer).unwrap();
+            if let Err(e) = writer.write_all(serialized).await {
+                warn!("Failed to retransmit event: {:?}", e);
+                continue 'outer;
+            }
+        }
+
+        // Liveness of the link, any message from the server counts as a heartbeat. The server
+        // sends its settings with the InitBoard, so that both sides use the same ones.
+        let mut link = LinkTiming::default();
+        let mut last_rx = Instant::now();
+        let mut next_heartbeat = Instant::now() + Duration::from_millis(link.heartbeat_ms as u64);
+        let mut next_status = Instant::now();
 
         'inner: loop {
-            // Create futures for reading and receivng a button press update.
+            let heartbeat_interval = Duration::from_millis(link.heartbeat_ms as u64);
+            let heartbeat_timeout = Duration::from_millis(link.timeout_ms as u64);
+
+            // Create futures for reading, receivng a button press update and the next timer.
             let read_fut = reader.read(msg_buffer.as_buf());
             let button_fut = button_channel.receive();
+            let timer_fut = Timer::at(
+                next_heartbeat
+                    .min(next_status)
+                    .min(last_rx + heartbeat_timeout),
+            );
 
-            match select(read_fut, button_fut).await {
-                Either::First(read_res) => match read_res {
+            match select3(read_fut, button_fut, timer_fut).await {
+                Either3::First(read_res) => match read_res {
                     Ok(0) => {
-                        // Nothing new read, try to deserialize.
-                        if !msg_buffer.process_msgs_ok(handle_message) {
-                            if remaining_err_on_zero <= 0 {
-                                warn!("Reconnecting");
-                                continue 'outer;
-                            }
-                            remaining_err_on_zero -= 1;
-                        }
+                        // Framing resynchronizes on its own, so EOF is the only reason left.
+                        warn!("Connection closed by server, reconnecting");
+                        continue 'outer;
                     }
                     Ok(num_read) => {
+                        last_rx = Instant::now();
+                        let rx_us = last_rx.as_micros();
                         msg_buffer.cursor += num_read;
-                        msg_buffer.process_msgs_ok(handle_message);
+
+                        let mut replies = Replies::new();
+                        if !msg_buffer.process_msgs_ok(|msg| {
+                            handle_message(
+                                msg,
+                                rx_us,
+                                &mut game_start_us,
+                                &mut link,
+                                &mut unacked,
+                                &mut replies,
+                            )
+                        }) {
+                            warn!("Dropped frames from server: {:?}", msg_buffer.stats);
+                        }
+
+                        for mut reply in replies {
+                            if let BoardToServer::Pong(pong) = &mut reply {
+                                pong.board_tx_us = Instant::now().as_micros();
+                            }
+                            let serialized = encode_frame(&reply, &mut serialize_buffer).unwrap();
+                            if let Err(e) = writer.write_all(serialized).await {
+                                warn!("Failed to send reply: {:?}", e);
+                                continue 'outer;
+                            }
+                        }
                     }
                     Err(e) => {
                         warn!("Error while reading: {}", e);
@@ -121,25 +226,66 @@ pub async fn tcp_task(stack: &'static Stack<Device>, button_channel: &'static Bu
                         }
                     }
                 },
-                Either::Second((button_id, press_time)) => {
-                    let millis_since_init =
-                        (press_time as u32).saturating_sub(INIT_TIME.load(Ordering::Acquire));
-                    if millis_since_init == 0 {
-                        warn!(
-                            "Button press {} registered before last reset, skipping",
+                Either3::Second(event) => {
+                    // Including the event just received.
+                    let queued = (button_channel.len() + 1) as u8;
+                    button_queue_peak = button_queue_peak.max(queued);
+
+                    let ButtonEvent {
+                        button_id,
+                        time_us,
+                        edge,
+                        locked,
+                        first_after_arm,
+                    } = event;
+                    if first_after_arm {
+                        info!("First press after arming: {} at {} us", button_id, time_us);
+                    }
+
+                    let (micros_since_init, before_game) = match game_start_us {
+                        Some(start_us) if time_us >= start_us => (time_us - start_us, false),
+                        _ => (0, true),
+                    };
+                    if before_game {
+                        info!(
+                            "Button event of {} registered before current game",
                             button_id
                         );
-                        continue 'inner;
                     }
 
-                    let message = Message::ButtonPress(ButtonPress {
-                        button_id,
-                        millis_since_init,
-                    });
+                    let seq = next_seq;
+                    next_seq = next_seq.wrapping_add(1);
+                    let message = match edge {
+                        Edge::Pressed => BoardToServer::ButtonPress(ButtonPress {
+                            button_id,
+                            board_time_us: time_us,
+                            micros_since_init,
+                            before_game,
+                            locked,
+                            first_after_arm,
+                            seq,
+                        }),
+                        Edge::Released { held_us } => BoardToServer::ButtonRelease(ButtonRelease {
+                            button_id,
+                            board_time_us: time_us,
+                            micros_since_init,
+                            before_game,
+                            locked,
+                            held_us,
+                            seq,
+                        }),
+                    };
+
+                    // Keep the event until the server acknowledged it.
+                    if let Err(message) = unacked.push_back(message.clone()) {
+                        let dropped = unacked.pop_front();
+                        warn!("Too many unacknowledged events, dropping {:?}", dropped);
+                        unacked.push_back(message).ok();
+                    }
 
                     debug!("Sending message: {:?}", message);
 
-                    let serialized = to_slice(&message, &mut serialize_buffer).unwrap();
+                    let serialized = encode_frame(&message, &mut serialize_buffer).unwrap();
 
                     let r = writer.write_all(serialized).await;
                     if let Err(e) = r {
@@ -151,28 +297,143 @@ pub async fn tcp_task(stack: &'static Stack<Device>, button_channel: &'static Bu
 
                         continue 'inner;
                     }
+                    if matches!(message, BoardToServer::ButtonPress(_)) {
+                        presses_sent = presses_sent.wrapping_add(1);
+                    }
+                }
+                Either3::Third(()) => {
+                    let now = Instant::now();
+                    if now >= last_rx + heartbeat_timeout {
+                        warn!(
+                            "No message from server for {:?}, reconnecting",
+                            heartbeat_timeout
+                        );
+                        continue 'outer;
+                    }
+
+                    if now >= next_heartbeat {
+                        next_heartbeat = now + heartbeat_interval;
+                        let serialized =
+                            encode_frame(&BoardToServer::Heartbeat, &mut serialize_buffer).unwrap();
+                        if let Err(e) = writer.write_all(serialized).await {
+                            warn!("Failed to send heartbeat: {:?}", e);
+                            continue 'outer;
+                        }
+                    }
+
+                    if now >= next_status {
+                        next_status = now + STATUS_INTERVAL;
+                        let status = BoardToServer::BoardStatus(BoardStatus {
+                            uptime_us: now.as_micros(),
+                            connected_us: (now - connected_at).as_micros(),
+                            reconnects: connections - 1,
+                            presses_sent,
+                            debounce_rejected: DEBOUNCE_REJECTED.load(Ordering::Relaxed),
+                            led_queue_overflows: LED_QUEUE_OVERFLOWS.load(Ordering::Relaxed),
+                            button_queue_len: button_channel.len() as u8,
+                            button_queue_peak,
+                            frame_stats: msg_buffer.stats,
+                        });
+                        debug!("Sending status: {:?}", status);
+                        let serialized = encode_frame(&status, &mut serialize_buffer).unwrap();
+                        if let Err(e) = writer.write_all(serialized).await {
+                            warn!("Failed to send status: {:?}", e);
+                            continue 'outer;
+                        }
+                    }
                 }
             }
         }
     }
 }
 
-fn handle_message(message: Message) {
+fn handle_message(
+    message: ServerToBoard,
+    rx_us: u64,
+    game_start_us: &mut Option<u64>,
+    link: &mut LinkTiming,
+    unacked: &mut UnackedEvents,
+    replies: &mut Replies,
+) {
     match message {
-        Message::InitBoard | Message::InitReactionGame(_) => {
+        ServerToBoard::InitBoard(_) | ServerToBoard::InitReactionGame(_) => {
             info!("Received InitBoard instruction");
-            let instant_millis = Instant::now().as_millis() as u32;
-            INIT_TIME.store(instant_millis, Ordering::Release);
+            if let ServerToBoard::InitBoard(timing) = message {
+                *link = timing;
+            }
+            let now_us = Instant::now().as_micros();
+            *game_start_us = Some(now_us);
+            push_reply(replies, BoardToServer::InitAck(now_us));
+        }
+        ServerToBoard::Ping(ping) => {
+            debug!("Received {:?}", ping);
+            // The transmit time is filled in right before sending.
+            let pong = Pong {
+                id: ping.id,
+                server_tx_us: ping.server_tx_us,
+                board_rx_us: rx_us,
+                board_tx_us: rx_us,
+            };
+            push_reply(replies, BoardToServer::Pong(pong));
+        }
+        ServerToBoard::Heartbeat => {}
+        ServerToBoard::Arm(mask) => {
+            info!("Arming buttons {:#x}", mask);
+            ARMING.lock(|arming| arming.borrow_mut().arm(mask));
         }
-        Message::Ping(ping_nr) => {
-            info!("Received Ping({})", ping_nr);
+        ServerToBoard::Lock(lock) => {
+            info!("Locking buttons: {:?}", lock);
+            ARMING.lock(|arming| arming.borrow_mut().lock(lock));
         }
-        Message::ButtonPress(_) => {
-            warn!("Board should not be receiving ButtonPress data");
+        ServerToBoard::FirstPressWins(mode) => {
+            info!("First press wins: {:?}", mode);
+            ARMING.lock(|arming| arming.borrow_mut().set_first_press_wins(mode));
         }
-        Message::LedUpdate(update) => {
+        ServerToBoard::ConfigureButtons(configure) => {
+            info!("Configuring buttons: {:?}", configure);
+            report_button_config(replies, configure_buttons(configure));
+        }
+        ServerToBoard::GetButtonConfig => {
+            let config = BUTTON_CONFIG.lock(|config| *config.borrow());
+            report_button_config(replies, config);
+        }
+        ServerToBoard::LedUpdate(update) => {
             info!("Received LED update: {:?}", update);
-            LED_CHANGE_Q.enqueue(update).ok();
+            queue_led_command(LedCommand::Frame(update.into()));
+        }
+        ServerToBoard::LedFrame(frame) => {
+            info!("Received LED frame: {:?}", frame);
+            queue_led_command(LedCommand::Frame(frame));
+        }
+        ServerToBoard::LedEffect(effect) => {
+            info!("Received LED effect: {:?}", effect);
+            queue_led_command(LedCommand::Effect(effect));
         }
+        ServerToBoard::ButtonPressAck(seq) => {
+            // Acknowledgements are cumulative.
+            while unacked
+                .front()
+                .and_then(BoardToServer::seq)
+                .is_some_and(|event_seq| event_seq <= seq)
+            {
+                unacked.pop_front();
+            }
+        }
+    }
+}
+
+fn report_button_config(replies: &mut Replies, config: [ButtonConfig; NUM_BUTTONS]) {
+    for (button_id, config) in config.into_iter().enumerate() {
+        let report = ButtonConfigReport {
+            button_id: button_id as u8,
+            config,
+        };
+        push_reply(replies, BoardToServer::ButtonConfig(report));
+    }
+}
+
+fn push_reply(replies: &mut Replies, reply: BoardToServer) {
+    if let Err(reply) = replies.push(reply) {
+        warn!("Too many replies, dropping {:?}", reply);
     }
 }
@@ -0,0 +1,121 @@
+//! Arming and lockout of buttons, decided on the board without waiting for the server.
+use defmt::Format;
+use serde::{Deserialize, Serialize};
+
+/// Lock all buttons whose bit is set in `mask`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub struct Lock {
+    pub mask: u16,
+    pub mode: LockMode,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub enum LockMode {
+    /// Events of locked buttons are dropped on the board.
+    Discard,
+    /// Events of locked buttons are sent with `locked` set.
+    Tag,
+}
+
+/// What to do with a button event.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub enum Verdict {
+    Discard,
+    Forward {
+        locked: bool,
+        /// First press of an unlocked button since the last arm.
+        first_after_arm: bool,
+    },
+}
+
+/// Lockout state of all buttons. All buttons start unlocked and not armed.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Format)]
+pub struct Arming {
+    discard: u16,
+    tag: u16,
+    /// Set by [`Arming::arm`] until the next press of an unlocked button.
+    awaiting_first: bool,
+    /// Lock all other buttons with this mode after the first press.
+    first_press_wins: Option<LockMode>,
+}
+
+impl Arming {
+    pub const fn new() -> Self {
+        Self {
+            discard: 0,
+            tag: 0,
+            awaiting_first: false,
+            first_press_wins: None,
+        }
+    }
+
+    pub fn set_first_press_wins(&mut self, mode: Option<LockMode>) {
+        self.first_press_wins = mode;
+    }
+
+    pub fn first_press_wins(&self) -> bool {
+        self.first_press_wins.is_some()
+    }
+
+    /// Unlock the buttons in `mask` and wait for the first press.
+    pub fn arm(&mut self, mask: u16) {
+        self.discard &= !mask;
+        self.tag &= !mask;
+        self.awaiting_first = true;
+    }
+
+    pub fn lock(&mut self, lock: Lock) {
+        match lock.mode {
+            LockMode::Discard => {
+                self.discard |= lock.mask;
+                self.tag &= !lock.mask;
+            }
+            LockMode::Tag => {
+                self.tag |= lock.mask;
+                self.discard &= !lock.mask;
+            }
+        }
+    }
+
+    pub fn is_locked(&self, button_id: u8) -> bool {
+        (self.discard | self.tag) & bit(button_id) != 0
+    }
+
+    /// Decide on a press, consuming the first press after an arm.
+    ///
+    /// With [`Arming::first_press_wins`], the first press locks all other buttons.
+    pub fn on_press(&mut self, button_id: u8) -> Verdict {
+        let verdict = self.on_release(button_id);
+        let Verdict::Forward { locked: false, .. } = verdict else {
+            return verdict;
+        };
+
+        let first_after_arm = core::mem::take(&mut self.awaiting_first);
+        if let (true, Some(mode)) = (first_after_arm, self.first_press_wins) {
+            self.lock(Lock {
+                mask: !bit(button_id),
+                mode,
+            });
+        }
+        Verdict::Forward {
+            locked: false,
+            first_after_arm,
+        }
+    }
+
+    /// Decide on a release, which is never the first press.
+    pub fn on_release(&self, button_id: u8) -> Verdict {
+        if self.discard & bit(button_id) != 0 {
+            Verdict::Discard
+        } else {
+            Verdict::Forward {
+                locked: self.tag & bit(button_id) != 0,
+                first_after_arm: false,
+            }
+        }
+    }
+}
+
+fn bit(button_id: u8) -> u16 {
+    1u16.checked_shl(button_id as u32).unwrap_or(0)
+}
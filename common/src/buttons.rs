@@ -0,0 +1,58 @@
+//! Electrical configuration of the buttons, changeable at runtime.
+use defmt::Format;
+use serde::{Deserialize, Serialize};
+
+/// Debounce time of buttons which were not configured.
+pub const DEFAULT_DEBOUNCE_US: u32 = 100_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub struct ButtonConfig {
+    /// Minimum time between two flanks to be considered a new press or release.
+    pub debounce_us: u32,
+    pub edge: ActiveEdge,
+    pub pull: Pull,
+}
+
+impl ButtonConfig {
+    /// Button which reads high while pressed.
+    pub const fn active_high(pull: Pull) -> Self {
+        Self {
+            debounce_us: DEFAULT_DEBOUNCE_US,
+            edge: ActiveEdge::Rising,
+            pull,
+        }
+    }
+}
+
+/// Flank at which a button counts as pressed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub enum ActiveEdge {
+    /// Pressed while the input reads high.
+    Rising,
+    /// Pressed while the input reads low.
+    Falling,
+    /// Every flank is a press, e.g. for toggle switches. No releases are reported.
+    Both,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+}
+
+/// Apply `config` to all buttons whose bit is set in `mask`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub struct ConfigureButtons {
+    pub mask: u16,
+    pub config: ButtonConfig,
+}
+
+/// Current configuration of one button, sent in response to [`ConfigureButtons`] and
+/// [`super::ServerToBoard::GetButtonConfig`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub struct ButtonConfigReport {
+    pub button_id: u8,
+    pub config: ButtonConfig,
+}
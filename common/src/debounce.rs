@@ -0,0 +1,87 @@
+//! Debouncing of button flanks, decided on the board from the raw input levels.
+use defmt::Format;
+
+use crate::buttons::{ActiveEdge, ButtonConfig};
+
+/// Debounced change of a button.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub enum Edge {
+    Pressed,
+    Released { held_us: u64 },
+}
+
+/// Flank rejected as bouncing, within the debounce time of the previous one.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub struct Bounce;
+
+/// Debounce state of a single button. Times are board clock in microseconds.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub struct Debounce {
+    config: ButtonConfig,
+    /// Level after the last accepted flank.
+    high: bool,
+    /// Time of the last flank, including flanks rejected as bouncing.
+    last_flank: u64,
+    /// Time of the last accepted press, `None` while released.
+    pressed_at: Option<u64>,
+    /// Set when a flank was rejected, the level is checked again once it settled.
+    unsettled: bool,
+}
+
+impl Debounce {
+    /// Start with the level read at `now`, which is checked again once the pull settled.
+    pub fn new(config: ButtonConfig, high: bool, now: u64) -> Self {
+        Self {
+            config,
+            high,
+            last_flank: now,
+            pressed_at: None,
+            unsettled: true,
+        }
+    }
+
+    /// Handle a flank at `now`, with `high` the level after it.
+    pub fn on_flank(&mut self, high: bool, now: u64) -> Result<Option<(Edge, u64)>, Bounce> {
+        let settled = now.saturating_sub(self.last_flank) > self.config.debounce_us as u64;
+        self.last_flank = now;
+        if !settled {
+            self.unsettled = true;
+            return Err(Bounce);
+        }
+        Ok(self.change(high, now))
+    }
+
+    /// Time at which the level of an unsettled button should be checked.
+    pub fn settle_deadline(&self) -> Option<u64> {
+        self.unsettled
+            .then_some(self.last_flank + self.config.debounce_us as u64 + 1)
+    }
+
+    /// Take over the settled level, e.g. the release of a press shorter than the debounce time.
+    pub fn settle(&mut self, high: bool) -> Option<(Edge, u64)> {
+        self.unsettled = false;
+        self.change(high, self.last_flank)
+    }
+
+    fn change(&mut self, high: bool, time_us: u64) -> Option<(Edge, u64)> {
+        if high == self.high {
+            return None;
+        }
+        self.high = high;
+
+        let pressed = match self.config.edge {
+            ActiveEdge::Rising => high,
+            ActiveEdge::Falling => !high,
+            // Every flank is a press, there is nothing to release.
+            ActiveEdge::Both => return Some((Edge::Pressed, time_us)),
+        };
+        if pressed {
+            self.pressed_at = Some(time_us);
+            Some((Edge::Pressed, time_us))
+        } else {
+            // Buttons held while being configured are only reported after the next press.
+            let held_us = time_us.saturating_sub(self.pressed_at.take()?);
+            Some((Edge::Released { held_us }, time_us))
+        }
+    }
+}
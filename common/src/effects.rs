@@ -0,0 +1,74 @@
+//! LED effects which the board animates on its own.
+//!
+//! Effects are pure functions of the time since they started, so the board only needs a tick
+//! and does not depend on network timing.
+use defmt::Format;
+use serde::{Deserialize, Serialize};
+
+/// Number of brightness steps of the software PWM used for [`EffectKind::Pulse`].
+///
+/// With a tick of 1 ms, this gives a PWM frequency of 100 Hz.
+pub const PWM_STEPS: u32 = 10;
+
+/// Effect to run on all LEDs whose bit is set in `mask`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub struct LedEffect {
+    pub mask: u16,
+    pub kind: EffectKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub enum EffectKind {
+    /// Blink `count` times with `period_ms`, then switch off. Blinks forever if `count` is 0.
+    Blink { period_ms: u16, count: u16 },
+    /// Fade in and out with `period_ms`, until replaced.
+    Pulse { period_ms: u16 },
+    /// Light the LEDs one after another for `step_ms` each, until replaced.
+    Chase { step_ms: u16 },
+    /// Blink `count` times with `period_ms`, then stay on.
+    FlashThenHold { period_ms: u16, count: u16 },
+}
+
+/// Level of an LED while running an effect.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub enum EffectLevel {
+    Running(bool),
+    /// The effect finished and the LED should stay at this level.
+    Done(bool),
+}
+
+impl EffectKind {
+    /// Level `elapsed_ms` after the start, for the LED at position `rank` of the `num` LEDs
+    /// running this effect.
+    pub fn level(&self, elapsed_ms: u32, rank: u8, num: u8) -> EffectLevel {
+        match *self {
+            EffectKind::Blink { period_ms, count } => blink(elapsed_ms, period_ms, count, false),
+            EffectKind::FlashThenHold { period_ms, count } => {
+                blink(elapsed_ms, period_ms, count.max(1), true)
+            }
+            EffectKind::Pulse { period_ms } => {
+                let period = (period_ms as u32).max(2);
+                let pos = elapsed_ms % period;
+                let half = period / 2;
+                let brightness = if pos < half {
+                    pos * PWM_STEPS / half
+                } else {
+                    (period - pos) * PWM_STEPS / half
+                };
+                EffectLevel::Running(elapsed_ms % PWM_STEPS < brightness)
+            }
+            EffectKind::Chase { step_ms } => {
+                let step = elapsed_ms / (step_ms as u32).max(1);
+                EffectLevel::Running(step % (num as u32).max(1) == rank as u32)
+            }
+        }
+    }
+}
+
+fn blink(elapsed_ms: u32, period_ms: u16, count: u16, hold: bool) -> EffectLevel {
+    let period = (period_ms as u32).max(2);
+    if count != 0 && elapsed_ms >= period * count as u32 {
+        return EffectLevel::Done(hold);
+    }
+    EffectLevel::Running(elapsed_ms % period < period / 2)
+}
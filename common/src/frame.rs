@@ -0,0 +1,235 @@
+//! Framing of messages on the wire.
+//!
+//! Each frame is the postcard encoding of a message followed by a little-endian CRC-16 over
+//! those bytes, COBS-encoded and terminated by [`FRAME_DELIMITER`].
+use defmt::{warn, Format};
+use postcard::{
+    from_bytes,
+    ser_flavors::{Cobs, Flavor, Slice},
+    serialize_with_flavor,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Delimiter terminating every frame on the wire.
+pub const FRAME_DELIMITER: u8 = 0x00;
+
+/// Upper bound for the size of an encoded frame, including delimiter.
+pub const MAX_FRAME_SIZE: usize = 128;
+
+/// Size of the checksum appended to every payload.
+const CRC_SIZE: usize = 2;
+
+/// Serialize a message into a frame, including the trailing [`FRAME_DELIMITER`].
+pub fn encode_frame<'a, M: Serialize>(
+    message: &M,
+    buf: &'a mut [u8],
+) -> postcard::Result<&'a mut [u8]> {
+    serialize_with_flavor(message, CrcFlavor::new(Cobs::try_new(Slice::new(buf))?))
+}
+
+/// Counters of decoded and dropped frames.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, Eq, PartialEq, Format)]
+pub struct FrameStats {
+    pub frames_ok: u32,
+    /// Frames with a checksum mismatch.
+    pub crc_errors: u32,
+    /// Frames with valid checksum which could not be decoded (e.g. invalid COBS or unknown
+    /// message).
+    pub decode_errors: u32,
+    /// Frames longer than the receive buffer.
+    pub overflows: u32,
+}
+
+impl FrameStats {
+    pub fn dropped(&self) -> u32 {
+        self.crc_errors + self.decode_errors + self.overflows
+    }
+}
+
+/// Buffer for a stream of frames as produced by [`encode_frame`].
+///
+/// A frame which cannot be decoded is dropped and decoding resumes after the next delimiter,
+/// so a single corrupt byte cannot desynchronize the stream.
+pub struct MsgBuffer<const BUF_SIZE: usize> {
+    pub cursor: usize,
+    pub buf: [u8; BUF_SIZE],
+    pub stats: FrameStats,
+    /// Set after an overflow, bytes are discarded until the next delimiter.
+    discarding: bool,
+}
+
+impl<const T: usize> MsgBuffer<T> {
+    /// Decode all complete frames and pass them to `callback`.
+    ///
+    /// Returns `false` if any frame had to be dropped.
+    pub fn process_msgs_ok<M, F>(&mut self, mut callback: F) -> bool
+    where
+        M: DeserializeOwned,
+        F: FnMut(M),
+    {
+        self.process_payloads(|payload| match decode_payload(payload) {
+            Some(message) => {
+                callback(message);
+                true
+            }
+            None => false,
+        })
+    }
+
+    /// Pass the payload of all complete frames with a valid checksum to `callback`, which
+    /// returns whether it could decode it.
+    ///
+    /// Returns `false` if any frame had to be dropped.
+    pub fn process_payloads<F>(&mut self, mut callback: F) -> bool
+    where
+        F: FnMut(&[u8]) -> bool,
+    {
+        let mut all_ok = true;
+        let mut frame_start = 0;
+
+        while let Some(frame_len) = self.buf[frame_start..self.cursor]
+            .iter()
+            .position(|&byte| byte == FRAME_DELIMITER)
+        {
+            let frame = &mut self.buf[frame_start..frame_start + frame_len];
+            frame_start += frame_len + 1;
+
+            if self.discarding {
+                // The rest of an overflowed frame, resume with the next one.
+                self.discarding = false;
+                continue;
+            }
+
+            // Empty frames may be used to resynchronize and carry no message.
+            if frame.is_empty() {
+                continue;
+            }
+
+            let decoded = match decode_frame(frame) {
+                Ok(payload) => callback(payload),
+                Err(FrameError::Crc) => {
+                    warn!(
+                        "Checksum mismatch in frame of {} bytes, skipping...",
+                        frame_len
+                    );
+                    self.stats.crc_errors += 1;
+                    all_ok = false;
+                    continue;
+                }
+                Err(FrameError::Decode) => false,
+            };
+            if decoded {
+                self.stats.frames_ok += 1;
+            } else {
+                warn!(
+                    "Could not deserialize frame of {} bytes, skipping...",
+                    frame_len
+                );
+                self.stats.decode_errors += 1;
+                all_ok = false;
+            }
+        }
+
+        // Move the start of an incomplete frame to the front of the buffer.
+        self.buf.copy_within(frame_start..self.cursor, 0);
+        self.cursor -= frame_start;
+
+        if self.cursor == T {
+            warn!("Frame exceeds buffer of {} bytes, discarding...", T);
+            self.cursor = 0;
+            self.discarding = true;
+            self.stats.overflows += 1;
+            all_ok = false;
+        }
+
+        all_ok
+    }
+
+    pub fn as_buf(&mut self) -> &mut [u8] {
+        &mut self.buf[self.cursor..]
+    }
+}
+
+impl<const T: usize> Default for MsgBuffer<T> {
+    fn default() -> Self {
+        Self {
+            cursor: 0,
+            buf: [0u8; T],
+            stats: FrameStats::default(),
+            discarding: false,
+        }
+    }
+}
+
+enum FrameError {
+    Crc,
+    Decode,
+}
+
+/// Decode a single frame without its delimiter in place, returning its payload.
+fn decode_frame(frame: &mut [u8]) -> Result<&[u8], FrameError> {
+    let len = cobs::decode_in_place(frame).map_err(|_| FrameError::Decode)?;
+    if len < CRC_SIZE {
+        return Err(FrameError::Decode);
+    }
+
+    let (payload, crc) = frame[..len].split_at(len - CRC_SIZE);
+    if crc16(payload) != u16::from_le_bytes([crc[0], crc[1]]) {
+        return Err(FrameError::Crc);
+    }
+    Ok(payload)
+}
+
+/// Decode the payload of a frame as passed by [`MsgBuffer::process_payloads`].
+pub fn decode_payload<M: DeserializeOwned>(payload: &[u8]) -> Option<M> {
+    from_bytes(payload).ok()
+}
+
+/// Serialization flavor appending a CRC-16 of all serialized bytes.
+struct CrcFlavor<F> {
+    inner: F,
+    crc: u16,
+}
+
+impl<F> CrcFlavor<F> {
+    fn new(inner: F) -> Self {
+        Self {
+            inner,
+            crc: CRC_INIT,
+        }
+    }
+}
+
+impl<F: Flavor> Flavor for CrcFlavor<F> {
+    type Output = F::Output;
+
+    fn try_push(&mut self, data: u8) -> postcard::Result<()> {
+        self.crc = crc16_update(self.crc, data);
+        self.inner.try_push(data)
+    }
+
+    fn finalize(mut self) -> postcard::Result<Self::Output> {
+        self.inner.try_extend(&self.crc.to_le_bytes())?;
+        self.inner.finalize()
+    }
+}
+
+const CRC_INIT: u16 = 0xffff;
+
+/// CRC-16/IBM-3740 (also known as CRC-16/CCITT-FALSE).
+fn crc16(data: &[u8]) -> u16 {
+    data.iter()
+        .fold(CRC_INIT, |crc, &byte| crc16_update(crc, byte))
+}
+
+fn crc16_update(crc: u16, byte: u8) -> u16 {
+    let mut crc = crc ^ ((byte as u16) << 8);
+    for _ in 0..8 {
+        crc = if crc & 0x8000 != 0 {
+            (crc << 1) ^ 0x1021
+        } else {
+            crc << 1
+        };
+    }
+    crc
+}
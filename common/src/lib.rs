@@ -1,24 +1,249 @@
 #![no_std]
 
-use defmt::{warn, Format};
-use postcard::take_from_bytes;
+use defmt::Format;
 use serde::{Deserialize, Serialize};
 
+pub mod arming;
+pub mod buttons;
+pub mod debounce;
+pub mod effects;
+mod frame;
+
+pub use arming::{Lock, LockMode};
+pub use buttons::{ButtonConfig, ButtonConfigReport, ConfigureButtons};
+pub use effects::{EffectKind, LedEffect};
+pub use frame::{
+    decode_payload, encode_frame, FrameStats, MsgBuffer, FRAME_DELIMITER, MAX_FRAME_SIZE,
+};
+
 pub const SERVER_ADDR: [u8; 4] = [192, 168, 100, 1];
 
+/// Version of the wire protocol spoken between board and server.
+///
+/// Bump this once per release whose encoding of [`BoardToServer`], [`ServerToBoard`] or their
+/// framing changed in a way that deployed firmware cannot decode, not for every change in
+/// between. The golden-byte tests in `tests/golden.rs` pin the current encoding.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Optional features a board can advertise in [`Hello::features`].
+pub mod features {
+    /// The board drives its buzzer LEDs from [`super::LedUpdate`] messages.
+    pub const LED_UPDATE: u32 = 1 << 0;
+    /// The board applies [`super::LedFrame`] messages to all LEDs at once.
+    pub const LED_FRAME: u32 = 1 << 1;
+    /// The board animates [`super::LedEffect`]s on its own.
+    pub const LED_EFFECTS: u32 = 1 << 2;
+    /// The board reports [`super::ButtonRelease`]s.
+    pub const BUTTON_RELEASE: u32 = 1 << 3;
+    /// The board handles [`super::ServerToBoard::Arm`] and [`super::ServerToBoard::Lock`].
+    pub const ARMING: u32 = 1 << 4;
+    /// The board handles [`super::ServerToBoard::FirstPressWins`].
+    pub const FIRST_PRESS_WINS: u32 = 1 << 5;
+    /// The board handles [`super::ServerToBoard::ConfigureButtons`] and
+    /// [`super::ServerToBoard::GetButtonConfig`].
+    pub const BUTTON_CONFIG: u32 = 1 << 6;
+}
+
+// New variants must only be appended, otherwise the discriminants of existing variants change.
+
+/// Messages sent from the board to the server.
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Format)]
-pub enum Message {
-    InitBoard,
-    InitReactionGame(u32),
-    Ping(u32),
+pub enum BoardToServer {
+    /// Must stay the first variant, so that the handshake can be decoded across versions.
+    Hello(Hello),
     ButtonPress(ButtonPress),
+    Pong(Pong),
+    /// Board clock in microseconds at which `micros_since_init` restarted from 0.
+    InitAck(u64),
+    /// Sent periodically so that the server notices a dead link.
+    Heartbeat,
+    ButtonRelease(ButtonRelease),
+    /// Sent periodically so that the health of the board can be watched without a probe.
+    BoardStatus(BoardStatus),
+    ButtonConfig(ButtonConfigReport),
+}
+
+impl BoardToServer {
+    /// Sequence number of button events, which are acknowledged by the server.
+    pub fn seq(&self) -> Option<u32> {
+        match self {
+            BoardToServer::ButtonPress(press) => Some(press.seq),
+            BoardToServer::ButtonRelease(release) => Some(release.seq),
+            _ => None,
+        }
+    }
+}
+
+/// Messages sent from the server to the board.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Format)]
+pub enum ServerToBoard {
+    /// Sent after the handshake, with the liveness settings of the server.
+    InitBoard(LinkTiming),
+    InitReactionGame(u32),
+    Ping(Ping),
     LedUpdate(LedUpdate),
+    /// Acknowledges all button events up to and including this sequence number.
+    ButtonPressAck(u32),
+    LedFrame(LedFrame),
+    LedEffect(LedEffect),
+    /// Sent periodically so that the board notices a dead link.
+    Heartbeat,
+    /// Unlock the buttons in the mask and report the next press as the first after arming.
+    Arm(u16),
+    Lock(Lock),
+    /// After arming, light the LED of the first pressed button and lock all others with the
+    /// given mode. Disabled with `None`.
+    FirstPressWins(Option<LockMode>),
+    /// Answered with the new configuration of every button.
+    ConfigureButtons(ConfigureButtons),
+    /// Answered with the configuration of every button.
+    GetButtonConfig,
 }
 
+/// Protocol version of a [`BoardToServer::Hello`] payload, readable for any protocol version.
+///
+/// `None` if the payload is no `Hello`.
+pub fn hello_version(payload: &[u8]) -> Option<u16> {
+    /// Only the start of [`BoardToServer`], the rest of the payload is ignored.
+    #[derive(Deserialize)]
+    enum Prefix {
+        Hello { protocol_version: u16 },
+    }
+    let Prefix::Hello { protocol_version } = decode_payload(payload)?;
+    Some(protocol_version)
+}
+
+/// First message sent by the board after connecting, describing what it is and can do.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Format)]
+pub struct Hello {
+    /// Must stay the first field so that it can be read regardless of later changes.
+    pub protocol_version: u16,
+    pub firmware_version: FirmwareVersion,
+    pub board_id: u32,
+    pub num_buttons: u8,
+    pub num_leds: u8,
+    /// Bitmask of [`features`].
+    pub features: u32,
+    /// Random per boot, press sequence numbers restart when it changes.
+    pub boot_id: u32,
+}
+
+impl Hello {
+    pub fn supports(&self, feature: u32) -> bool {
+        self.features & feature == feature
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+/// Resolution of board times, whose clock ticks at 32,768 Hz.
+///
+/// Board times are given in microseconds but only change in steps of about 30.5 µs, so
+/// differences below this resolution are ties.
+pub const BOARD_CLOCK_RESOLUTION_US: u64 = 31;
+
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Format)]
 pub struct ButtonPress {
     pub button_id: u8,
-    pub millis_since_init: u32,
+    /// Board clock at the press, in microseconds since boot, with a resolution of
+    /// [`BOARD_CLOCK_RESOLUTION_US`].
+    pub board_time_us: u64,
+    /// Time since the current game was initialized, 0 if `before_game` is set. Same resolution
+    /// as `board_time_us`.
+    pub micros_since_init: u64,
+    /// The press happened before the current game was initialized.
+    pub before_game: bool,
+    /// The button was locked with [`LockMode::Tag`].
+    pub locked: bool,
+    /// First press of an unlocked button since the last [`ServerToBoard::Arm`].
+    pub first_after_arm: bool,
+    /// Sequence number per boot, starting at 1, used for acknowledgement and deduplication.
+    pub seq: u32,
+}
+
+/// Health counters of the board since boot.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Format)]
+pub struct BoardStatus {
+    /// Board clock in microseconds since boot.
+    pub uptime_us: u64,
+    /// Time in microseconds since the current connection to the server was established.
+    pub connected_us: u64,
+    /// Connections to the server established after the first one.
+    pub reconnects: u32,
+    /// Button presses sent to the server, not counting retransmissions.
+    pub presses_sent: u32,
+    /// Flanks rejected by the debounce filter.
+    pub debounce_rejected: u32,
+    /// LED commands dropped because the LED queue was full.
+    pub led_queue_overflows: u32,
+    /// Button events waiting to be sent.
+    pub button_queue_len: u8,
+    /// Most button events waiting at once.
+    pub button_queue_peak: u8,
+    /// Frames received from the server on the current connection, including dropped ones.
+    pub frame_stats: FrameStats,
+}
+
+/// Release of a button, debounced like presses and sharing their sequence numbers.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Format)]
+pub struct ButtonRelease {
+    pub button_id: u8,
+    /// Board clock at the release, in microseconds since boot, with a resolution of
+    /// [`BOARD_CLOCK_RESOLUTION_US`].
+    pub board_time_us: u64,
+    /// Time since the current game was initialized, 0 if `before_game` is set.
+    pub micros_since_init: u64,
+    /// The release happened before the current game was initialized.
+    pub before_game: bool,
+    /// The button was locked with [`LockMode::Tag`].
+    pub locked: bool,
+    /// Time since the matching press, in microseconds.
+    pub held_us: u64,
+    pub seq: u32,
+}
+
+/// Liveness settings of a connection, used by the board from [`ServerToBoard::InitBoard`] on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub struct LinkTiming {
+    /// Interval of heartbeats.
+    pub heartbeat_ms: u32,
+    /// Time without any message after which the connection is considered dead.
+    pub timeout_ms: u32,
+}
+
+impl Default for LinkTiming {
+    /// Used by the board until the server sent its own.
+    fn default() -> Self {
+        Self {
+            heartbeat_ms: 1000,
+            timeout_ms: 3000,
+        }
+    }
+}
+
+/// Clock synchronization request, answered by the board with a [`Pong`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub struct Ping {
+    pub id: u32,
+    /// Server clock when sending, in microseconds.
+    pub server_tx_us: u64,
+}
+
+/// Answer to a [`Ping`] with the board clock when receiving and answering it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub struct Pong {
+    pub id: u32,
+    /// Echo of [`Ping::server_tx_us`].
+    pub server_tx_us: u64,
+    /// Board clock when the ping was received, in microseconds.
+    pub board_rx_us: u64,
+    /// Board clock when the pong was sent, in microseconds.
+    pub board_tx_us: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Format)]
@@ -27,60 +252,66 @@ pub struct LedUpdate {
     pub on: bool,
 }
 
-pub struct MsgBuffer<const BUF_SIZE: usize> {
-    pub cursor: usize,
-    pub buf: [u8; BUF_SIZE],
-}
-
-impl<const T: usize> MsgBuffer<T> {
-    pub fn process_msgs_ok<F>(&mut self, callback: F) -> bool
-    where
-        F: Fn(Message),
-    {
-        let mut to_deserialize = &self.buf[0..self.cursor];
-
-        loop {
-            match take_from_bytes::<Message>(to_deserialize) {
-                Ok((message, unused)) => {
-                    to_deserialize = unused;
-                    callback(message);
-
-                    if to_deserialize.is_empty() {
-                        self.cursor = 0;
-                        return true;
-                    }
-                }
-                Err(_) => {
-                    warn!("Could not deserialize buffer, skipping...");
-
-                    // Example: cursor_pos at 12, read 8 bytes, 4 left over
-                    // ---- ---- | ---- |
-                    //                  cursor_pos: 12
-                    // -> copy from: 8..12 to 0..4
-                    let left_over_len = to_deserialize.len();
-                    let copy_start_idx = self.cursor - left_over_len;
-
-                    for idx in 0..left_over_len {
-                        self.buf[idx] = self.buf[copy_start_idx + idx];
-                    }
-                    self.cursor = left_over_len;
-
-                    return false;
-                }
-            }
+/// Update of several LEDs which is applied at once.
+///
+/// Bit `i` refers to the LED of button `i`. LEDs with their bit set in `mask` are switched to the
+/// state of the same bit in `on`, all others keep their state.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, Eq, PartialEq, Format)]
+pub struct LedFrame {
+    pub mask: u16,
+    pub on: u16,
+}
+
+impl LedFrame {
+    /// Frame switching all of the first `num_leds` LEDs.
+    pub fn all(num_leds: u8, on: bool) -> Self {
+        let mask = 1u16
+            .checked_shl(num_leds as u32)
+            .map_or(u16::MAX, |bit| bit - 1);
+        Self {
+            mask,
+            on: if on { mask } else { 0 },
+        }
+    }
+
+    /// Add a single update, overriding earlier state for the same LED.
+    pub fn set(&mut self, update: &LedUpdate) {
+        let Some(bit) = 1u16.checked_shl(update.button_id as u32) else {
+            return;
+        };
+        self.mask |= bit;
+        if update.on {
+            self.on |= bit;
+        } else {
+            self.on &= !bit;
         }
     }
 
-    pub fn as_buf(&mut self) -> &mut [u8] {
-        &mut self.buf[self.cursor..]
+    /// Apply `other` on top of this frame.
+    pub fn merge(&mut self, other: &LedFrame) {
+        self.mask |= other.mask;
+        self.on = (self.on & !other.mask) | (other.on & other.mask);
+    }
+
+    /// Target state of LED `idx`, `None` if the frame does not touch it.
+    pub fn get(&self, idx: usize) -> Option<bool> {
+        let bit = 1u16.checked_shl(idx as u32)?;
+        (self.mask & bit != 0).then_some(self.on & bit != 0)
+    }
+
+    /// The frame as individual updates, for boards without [`features::LED_FRAME`].
+    pub fn updates(&self) -> impl Iterator<Item = LedUpdate> + '_ {
+        (0..u16::BITS as u8).filter_map(|button_id| {
+            self.get(button_id as usize)
+                .map(|on| LedUpdate { button_id, on })
+        })
     }
 }
 
-impl<const T: usize> Default for MsgBuffer<T> {
-    fn default() -> Self {
-        Self {
-            cursor: 0,
-            buf: [0u8; T],
-        }
+impl From<LedUpdate> for LedFrame {
+    fn from(update: LedUpdate) -> Self {
+        let mut frame = Self::default();
+        frame.set(&update);
+        frame
     }
 }
@@ -0,0 +1,81 @@
+use common::arming::{Arming, Verdict};
+use common::{Lock, LockMode};
+
+const FORWARD: Verdict = Verdict::Forward {
+    locked: false,
+    first_after_arm: false,
+};
+
+#[test]
+fn only_first_press_after_arm_is_marked() {
+    let mut arming = Arming::default();
+    assert_eq!(arming.on_press(0), FORWARD);
+
+    arming.arm(0x3f);
+    assert_eq!(
+        arming.on_press(2),
+        Verdict::Forward {
+            locked: false,
+            first_after_arm: true,
+        }
+    );
+    assert_eq!(arming.on_press(1), FORWARD);
+}
+
+#[test]
+fn locked_buttons_are_discarded_or_tagged() {
+    let mut arming = Arming::default();
+    arming.lock(Lock {
+        mask: 1 << 1,
+        mode: LockMode::Discard,
+    });
+    arming.lock(Lock {
+        mask: 1 << 2,
+        mode: LockMode::Tag,
+    });
+    arming.arm(1 << 0);
+
+    assert_eq!(arming.on_press(1), Verdict::Discard);
+    assert_eq!(arming.on_release(1), Verdict::Discard);
+    // Locked presses do not consume the first press.
+    assert_eq!(
+        arming.on_press(2),
+        Verdict::Forward {
+            locked: true,
+            first_after_arm: false,
+        }
+    );
+    assert_eq!(
+        arming.on_press(0),
+        Verdict::Forward {
+            locked: false,
+            first_after_arm: true,
+        }
+    );
+
+    arming.arm(0xffff);
+    assert!(!arming.is_locked(1));
+    assert!(!arming.is_locked(2));
+}
+
+#[test]
+fn first_press_wins_locks_other_buttons() {
+    let mut arming = Arming::new();
+    arming.set_first_press_wins(Some(LockMode::Discard));
+    arming.arm(0xffff);
+
+    assert_eq!(
+        arming.on_press(4),
+        Verdict::Forward {
+            locked: false,
+            first_after_arm: true,
+        }
+    );
+    assert!(!arming.is_locked(4));
+    assert_eq!(arming.on_press(0), Verdict::Discard);
+    assert_eq!(arming.on_press(4), FORWARD);
+
+    // Arming again reopens the round for all buttons.
+    arming.arm(0xffff);
+    assert!(!arming.is_locked(0));
+}
@@ -0,0 +1,123 @@
+use common::buttons::{ActiveEdge, ButtonConfig, Pull};
+use common::debounce::{Bounce, Debounce, Edge};
+
+const DEBOUNCE_US: u32 = 1000;
+
+fn config(edge: ActiveEdge) -> ButtonConfig {
+    ButtonConfig {
+        debounce_us: DEBOUNCE_US,
+        edge,
+        pull: Pull::Down,
+    }
+}
+
+/// Button read low at boot, once the initial level settled.
+fn released(edge: ActiveEdge) -> Debounce {
+    let mut debounce = Debounce::new(config(edge), false, 0);
+    assert_eq!(debounce.settle_deadline(), Some(DEBOUNCE_US as u64 + 1));
+    assert_eq!(debounce.settle(false), None);
+    assert_eq!(debounce.settle_deadline(), None);
+    debounce
+}
+
+#[test]
+fn bouncing_flanks_are_suppressed() {
+    let mut debounce = released(ActiveEdge::Rising);
+    assert_eq!(
+        debounce.on_flank(true, 5000),
+        Ok(Some((Edge::Pressed, 5000)))
+    );
+    assert_eq!(debounce.on_flank(false, 5100), Err(Bounce));
+    assert_eq!(debounce.on_flank(true, 5200), Err(Bounce));
+
+    // The level settled where it was, so there is no further edge.
+    assert_eq!(
+        debounce.settle_deadline(),
+        Some(5200 + DEBOUNCE_US as u64 + 1)
+    );
+    assert_eq!(debounce.settle(true), None);
+    assert_eq!(debounce.settle_deadline(), None);
+
+    assert_eq!(
+        debounce.on_flank(false, 50_000),
+        Ok(Some((Edge::Released { held_us: 45_000 }, 50_000)))
+    );
+}
+
+#[test]
+fn release_within_debounce_time_is_reported_once_settled() {
+    let mut debounce = released(ActiveEdge::Rising);
+    assert_eq!(
+        debounce.on_flank(true, 5000),
+        Ok(Some((Edge::Pressed, 5000)))
+    );
+    assert_eq!(debounce.on_flank(false, 5300), Err(Bounce));
+
+    // Reported at the time of the last flank, not when the level was checked.
+    assert_eq!(
+        debounce.settle(false),
+        Some((Edge::Released { held_us: 300 }, 5300))
+    );
+}
+
+#[test]
+fn release_reports_the_hold_duration() {
+    let mut debounce = released(ActiveEdge::Rising);
+    assert_eq!(
+        debounce.on_flank(true, 10_000),
+        Ok(Some((Edge::Pressed, 10_000)))
+    );
+    assert_eq!(
+        debounce.on_flank(false, 510_000),
+        Ok(Some((Edge::Released { held_us: 500_000 }, 510_000)))
+    );
+}
+
+#[test]
+fn falling_edge_presses_while_low() {
+    let mut debounce = Debounce::new(config(ActiveEdge::Falling), true, 0);
+    assert_eq!(debounce.settle(true), None);
+
+    assert_eq!(
+        debounce.on_flank(false, 5000),
+        Ok(Some((Edge::Pressed, 5000)))
+    );
+    assert_eq!(
+        debounce.on_flank(true, 8000),
+        Ok(Some((Edge::Released { held_us: 3000 }, 8000)))
+    );
+}
+
+#[test]
+fn both_edges_press_without_release() {
+    let mut debounce = released(ActiveEdge::Both);
+    assert_eq!(
+        debounce.on_flank(true, 5000),
+        Ok(Some((Edge::Pressed, 5000)))
+    );
+    assert_eq!(
+        debounce.on_flank(false, 8000),
+        Ok(Some((Edge::Pressed, 8000)))
+    );
+    assert_eq!(
+        debounce.on_flank(false, 8000 + 2 * DEBOUNCE_US as u64),
+        Ok(None)
+    );
+}
+
+#[test]
+fn button_held_during_config_change_is_not_released() {
+    // The config was applied while the button was held, so the press was never reported.
+    let mut debounce = Debounce::new(config(ActiveEdge::Rising), true, 0);
+    assert_eq!(debounce.settle(true), None);
+    assert_eq!(debounce.on_flank(false, 5000), Ok(None));
+
+    assert_eq!(
+        debounce.on_flank(true, 8000),
+        Ok(Some((Edge::Pressed, 8000)))
+    );
+    assert_eq!(
+        debounce.on_flank(false, 9500),
+        Ok(Some((Edge::Released { held_us: 1500 }, 9500)))
+    );
+}
@@ -0,0 +1,50 @@
+//! Tests for the timing of LED effects.
+use common::effects::{EffectKind, EffectLevel};
+
+#[test]
+fn blink_then_off() {
+    let blink = EffectKind::Blink {
+        period_ms: 100,
+        count: 2,
+    };
+    assert_eq!(blink.level(0, 0, 1), EffectLevel::Running(true));
+    assert_eq!(blink.level(50, 0, 1), EffectLevel::Running(false));
+    assert_eq!(blink.level(120, 0, 1), EffectLevel::Running(true));
+    assert_eq!(blink.level(200, 0, 1), EffectLevel::Done(false));
+}
+
+#[test]
+fn flash_then_hold() {
+    let flash = EffectKind::FlashThenHold {
+        period_ms: 100,
+        count: 1,
+    };
+    assert_eq!(flash.level(60, 0, 1), EffectLevel::Running(false));
+    assert_eq!(flash.level(100, 0, 1), EffectLevel::Done(true));
+}
+
+#[test]
+fn chase_moves_through_leds() {
+    let chase = EffectKind::Chase { step_ms: 10 };
+    let lit = |elapsed| {
+        (0..3)
+            .filter(|&rank| chase.level(elapsed, rank, 3) == EffectLevel::Running(true))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(lit(0), [0]);
+    assert_eq!(lit(15), [1]);
+    assert_eq!(lit(35), [0]);
+}
+
+#[test]
+fn pulse_duty_cycle_follows_brightness() {
+    let pulse = EffectKind::Pulse { period_ms: 1000 };
+    let duty = |start: u32| {
+        (start..start + 10)
+            .filter(|&elapsed| pulse.level(elapsed, 0, 1) == EffectLevel::Running(true))
+            .count()
+    };
+    assert_eq!(duty(0), 0);
+    assert!(duty(495) >= 9);
+    assert!(duty(250) > 3 && duty(250) < 7);
+}
@@ -0,0 +1,71 @@
+//! Tests for decoding the framed byte stream in [`MsgBuffer`].
+use common::{encode_frame, MsgBuffer, ServerToBoard};
+
+fn feed<const N: usize>(buffer: &mut MsgBuffer<N>, bytes: &[u8]) {
+    buffer.as_buf()[..bytes.len()].copy_from_slice(bytes);
+    buffer.cursor += bytes.len();
+}
+
+fn frame(message: &ServerToBoard) -> Vec<u8> {
+    let mut buf = [0u8; 64];
+    encode_frame(message, &mut buf).unwrap().to_vec()
+}
+
+#[test]
+fn frame_split_across_reads() {
+    let mut buffer = MsgBuffer::<64>::default();
+    let bytes = frame(&ServerToBoard::InitReactionGame(3000));
+    let mut received: Vec<ServerToBoard> = Vec::new();
+
+    feed(&mut buffer, &bytes[..2]);
+    assert!(buffer.process_msgs_ok(|msg| received.push(msg)));
+    assert!(received.is_empty());
+
+    feed(&mut buffer, &bytes[2..]);
+    assert!(buffer.process_msgs_ok(|msg| received.push(msg)));
+    assert_eq!(received, [ServerToBoard::InitReactionGame(3000)]);
+    assert_eq!(buffer.cursor, 0);
+}
+
+#[test]
+fn resync_after_corrupt_frame() {
+    let mut buffer = MsgBuffer::<64>::default();
+    let mut corrupt = frame(&ServerToBoard::ButtonPressAck(1));
+    corrupt[0] = 0xff;
+    let mut received: Vec<ServerToBoard> = Vec::new();
+
+    feed(&mut buffer, &corrupt);
+    feed(&mut buffer, &frame(&ServerToBoard::ButtonPressAck(2)));
+    assert!(!buffer.process_msgs_ok(|msg| received.push(msg)));
+    assert_eq!(received, [ServerToBoard::ButtonPressAck(2)]);
+    assert_eq!(buffer.stats.decode_errors, 1);
+    assert_eq!(buffer.stats.frames_ok, 1);
+}
+
+#[test]
+fn drop_frame_with_checksum_mismatch() {
+    let mut buffer = MsgBuffer::<64>::default();
+    let mut corrupt = frame(&ServerToBoard::InitReactionGame(3000));
+    corrupt[2] ^= 0x01;
+    let mut received: Vec<ServerToBoard> = Vec::new();
+
+    feed(&mut buffer, &corrupt);
+    assert!(!buffer.process_msgs_ok(|msg| received.push(msg)));
+    assert!(received.is_empty());
+    assert_eq!(buffer.stats.crc_errors, 1);
+}
+
+#[test]
+fn resync_after_overflow() {
+    let mut buffer = MsgBuffer::<8>::default();
+    let mut received: Vec<ServerToBoard> = Vec::new();
+
+    feed(&mut buffer, &[0x01; 8]);
+    assert!(!buffer.process_msgs_ok(|msg| received.push(msg)));
+
+    feed(&mut buffer, &[0x01, 0x00]);
+    feed(&mut buffer, &frame(&ServerToBoard::ButtonPressAck(3)));
+    assert!(buffer.process_msgs_ok(|msg| received.push(msg)));
+    assert_eq!(received, [ServerToBoard::ButtonPressAck(3)]);
+    assert_eq!(buffer.stats.overflows, 1);
+}
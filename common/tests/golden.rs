@@ -0,0 +1,215 @@
+//! Golden-byte tests for the wire encoding of [`BoardToServer`] and [`ServerToBoard`].
+//!
+//! Deployed firmware is not updated together with the server. If one of these tests fails, the
+//! encoding changed. Update the expected bytes and make sure [`PROTOCOL_VERSION`] is bumped once
+//! for the next release.
+use std::fmt::Debug;
+
+use common::{
+    buttons::{ActiveEdge, Pull},
+    encode_frame, features, hello_version, BoardStatus, BoardToServer, ButtonConfig,
+    ButtonConfigReport, ButtonPress, ButtonRelease, ConfigureButtons, EffectKind, FirmwareVersion,
+    FrameStats, Hello, LedEffect, LedFrame, LedUpdate, LinkTiming, Lock, LockMode, Ping, Pong,
+    ServerToBoard, PROTOCOL_VERSION,
+};
+use postcard::{from_bytes, to_slice};
+use serde::{de::DeserializeOwned, Serialize};
+
+fn assert_golden<M>(msg: M, golden: &[u8])
+where
+    M: Serialize + DeserializeOwned + Debug + PartialEq,
+{
+    let mut buf = [0u8; 64];
+    let encoded = to_slice(&msg, &mut buf).unwrap();
+    assert_eq!(encoded, golden, "encoding of {msg:?} changed");
+    assert_eq!(from_bytes::<M>(golden).unwrap(), msg);
+}
+
+#[test]
+fn protocol_version() {
+    assert_eq!(PROTOCOL_VERSION, 1);
+}
+
+#[test]
+fn hello_version_of_other_shapes() {
+    // Hello of a firmware with fewer fields, followed by trailing bytes it does not know.
+    assert_eq!(hello_version(&[0x00, 0x05, 0x07]), Some(5));
+    assert_eq!(hello_version(&[0x00, 0xe8, 0x07, 0xff, 0xff]), Some(1000));
+    // Not a Hello, or too short for its version.
+    assert_eq!(hello_version(&[0x05]), None);
+    assert_eq!(hello_version(&[0x00]), None);
+}
+
+#[test]
+fn golden_board_to_server() {
+    assert_golden(
+        BoardToServer::Hello(Hello {
+            protocol_version: 1,
+            firmware_version: FirmwareVersion {
+                major: 0,
+                minor: 1,
+                patch: 0,
+            },
+            board_id: 0xdeadbeef,
+            num_buttons: 7,
+            num_leds: 6,
+            features: features::LED_UPDATE,
+            boot_id: 0x1234,
+        }),
+        &[
+            0x00, 0x01, 0x00, 0x01, 0x00, 0xef, 0xfd, 0xb6, 0xf5, 0x0d, 0x07, 0x06, 0x01, 0xb4,
+            0x24,
+        ],
+    );
+    assert_golden(
+        BoardToServer::ButtonPress(ButtonPress {
+            button_id: 3,
+            board_time_us: 5_000_000_000,
+            micros_since_init: 0,
+            before_game: true,
+            locked: false,
+            first_after_arm: true,
+            seq: 5,
+        }),
+        &[
+            0x01, 0x03, 0x80, 0xe4, 0x97, 0xd0, 0x12, 0x00, 0x01, 0x00, 0x01, 0x05,
+        ],
+    );
+    assert_golden(
+        BoardToServer::Pong(Pong {
+            id: 7,
+            server_tx_us: 1000,
+            board_rx_us: 300,
+            board_tx_us: 301,
+        }),
+        &[0x02, 0x07, 0xe8, 0x07, 0xac, 0x02, 0xad, 0x02],
+    );
+    assert_golden(BoardToServer::InitAck(128), &[0x03, 0x80, 0x01]);
+    assert_golden(BoardToServer::Heartbeat, &[0x04]);
+    assert_golden(
+        BoardToServer::ButtonRelease(ButtonRelease {
+            button_id: 3,
+            board_time_us: 5_000_500_000,
+            micros_since_init: 1_734_000,
+            before_game: false,
+            locked: true,
+            held_us: 500_000,
+            seq: 6,
+        }),
+        &[
+            0x05, 0x03, 0xa0, 0xa6, 0xb6, 0xd0, 0x12, 0xf0, 0xea, 0x69, 0x00, 0x01, 0xa0, 0xc2,
+            0x1e, 0x06,
+        ],
+    );
+    assert_golden(
+        BoardToServer::BoardStatus(BoardStatus {
+            uptime_us: 1_000_000,
+            connected_us: 300,
+            reconnects: 2,
+            presses_sent: 10,
+            debounce_rejected: 3,
+            led_queue_overflows: 0,
+            button_queue_len: 1,
+            button_queue_peak: 4,
+            frame_stats: FrameStats {
+                frames_ok: 200,
+                crc_errors: 1,
+                decode_errors: 0,
+                overflows: 2,
+            },
+        }),
+        &[
+            0x06, 0xc0, 0x84, 0x3d, 0xac, 0x02, 0x02, 0x0a, 0x03, 0x00, 0x01, 0x04, 0xc8, 0x01,
+            0x01, 0x00, 0x02,
+        ],
+    );
+    assert_golden(
+        BoardToServer::ButtonConfig(ButtonConfigReport {
+            button_id: 6,
+            config: ButtonConfig::active_high(Pull::Down),
+        }),
+        &[0x07, 0x06, 0xa0, 0x8d, 0x06, 0x00, 0x02],
+    );
+}
+
+#[test]
+fn golden_server_to_board() {
+    assert_golden(
+        ServerToBoard::InitBoard(LinkTiming {
+            heartbeat_ms: 1000,
+            timeout_ms: 3000,
+        }),
+        &[0x00, 0xe8, 0x07, 0xb8, 0x17],
+    );
+    assert_golden(ServerToBoard::InitReactionGame(3000), &[0x01, 0xb8, 0x17]);
+    assert_golden(
+        ServerToBoard::Ping(Ping {
+            id: 7,
+            server_tx_us: 1000,
+        }),
+        &[0x02, 0x07, 0xe8, 0x07],
+    );
+    assert_golden(
+        ServerToBoard::LedUpdate(LedUpdate {
+            button_id: 2,
+            on: true,
+        }),
+        &[0x03, 0x02, 0x01],
+    );
+    assert_golden(ServerToBoard::ButtonPressAck(9), &[0x04, 0x09]);
+    assert_golden(
+        ServerToBoard::LedFrame(LedFrame {
+            mask: 0x3f,
+            on: 0x05,
+        }),
+        &[0x05, 0x3f, 0x05],
+    );
+    assert_golden(
+        ServerToBoard::LedEffect(LedEffect {
+            mask: 0x01,
+            kind: EffectKind::FlashThenHold {
+                period_ms: 200,
+                count: 3,
+            },
+        }),
+        &[0x06, 0x01, 0x03, 0xc8, 0x01, 0x03],
+    );
+    assert_golden(ServerToBoard::Heartbeat, &[0x07]);
+    assert_golden(ServerToBoard::Arm(0x3f), &[0x08, 0x3f]);
+    assert_golden(
+        ServerToBoard::Lock(Lock {
+            mask: 0x04,
+            mode: LockMode::Tag,
+        }),
+        &[0x09, 0x04, 0x01],
+    );
+    assert_golden(
+        ServerToBoard::FirstPressWins(Some(LockMode::Discard)),
+        &[0x0a, 0x01, 0x00],
+    );
+    assert_golden(
+        ServerToBoard::ConfigureButtons(ConfigureButtons {
+            mask: 0x01,
+            config: ButtonConfig {
+                debounce_us: 20_000,
+                edge: ActiveEdge::Falling,
+                pull: Pull::Up,
+            },
+        }),
+        &[0x0b, 0x01, 0xa0, 0x9c, 0x01, 0x01, 0x01],
+    );
+    assert_golden(ServerToBoard::GetButtonConfig, &[0x0c]);
+}
+
+#[test]
+fn golden_frames() {
+    let mut buf = [0u8; 64];
+    assert_eq!(
+        encode_frame(&ServerToBoard::InitBoard(LinkTiming::default()), &mut buf).unwrap(),
+        &[0x01, 0x07, 0xe8, 0x07, 0xb8, 0x17, 0xc7, 0x60, 0x00]
+    );
+    assert_eq!(
+        encode_frame(&ServerToBoard::InitReactionGame(3000), &mut buf).unwrap(),
+        &[0x06, 0x01, 0xb8, 0x17, 0xde, 0x0e, 0x00]
+    );
+}
@@ -0,0 +1,46 @@
+//! Tests for combining LED changes into frames.
+use common::{LedFrame, LedUpdate};
+
+#[test]
+fn later_updates_of_the_same_led_win() {
+    let mut frame = LedFrame::from(LedUpdate {
+        button_id: 1,
+        on: true,
+    });
+    frame.set(&LedUpdate {
+        button_id: 2,
+        on: true,
+    });
+    frame.set(&LedUpdate {
+        button_id: 1,
+        on: false,
+    });
+    assert_eq!(
+        frame,
+        LedFrame {
+            mask: 0b110,
+            on: 0b100
+        }
+    );
+}
+
+#[test]
+fn merged_frames_override_overlapping_leds() {
+    let mut frame = LedFrame {
+        mask: 0b0111,
+        on: 0b0101,
+    };
+    frame.merge(&LedFrame {
+        mask: 0b1100,
+        on: 0b1000,
+    });
+    assert_eq!(
+        frame,
+        LedFrame {
+            mask: 0b1111,
+            on: 0b1001
+        }
+    );
+    assert_eq!(frame.get(2), Some(false));
+    assert_eq!(frame.get(4), None);
+}
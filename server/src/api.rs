@@ -0,0 +1,107 @@
+//! JSON endpoints exposing server state.
+use axum::{
+    extract::{Path, Query},
+    http::{HeaderMap, StatusCode},
+    Extension, Json,
+};
+use serde::Deserialize;
+
+use crate::{
+    game::GameMode,
+    history::{History, LeaderboardEntry, SessionDetails, SessionSummary},
+    players::{players_event, Player},
+    BoardInfo, UiBackendRouter,
+};
+
+pub async fn boards(Extension(uib_router): Extension<UiBackendRouter>) -> Json<Vec<BoardInfo>> {
+    let boards = uib_router.boards.lock().unwrap();
+    Json(boards.values().cloned().collect())
+}
+
+pub async fn players(Extension(uib_router): Extension<UiBackendRouter>) -> Json<Vec<Player>> {
+    let players = uib_router.players.lock().unwrap();
+    Json(players.players().to_vec())
+}
+
+/// Header carrying the host PIN on requests which change the server state.
+pub const HOST_PIN_HEADER: &str = "x-host-pin";
+
+/// Replace all players and push them to the websocket clients. Only the host may do this.
+pub async fn set_players(
+    Extension(uib_router): Extension<UiBackendRouter>,
+    headers: HeaderMap,
+    Json(players): Json<Vec<Player>>,
+) -> Result<Json<Vec<Player>>, (StatusCode, String)> {
+    let pin = headers
+        .get(HOST_PIN_HEADER)
+        .and_then(|pin| pin.to_str().ok());
+    if let Err(e) = uib_router.host_pin.check(pin) {
+        println!("Rejecting change of the players: {e:?}");
+        let (status, message) = e.status();
+        return Err((status, message.into()));
+    }
+    let result = uib_router.players.lock().unwrap().set(players.clone());
+    if let Err(e) = result {
+        println!("Could not save players: {e}");
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+    }
+    uib_router.frontend_tx.send(players_event(&uib_router)).ok();
+    Ok(Json(players))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LeaderboardQuery {
+    /// Only count sessions of this mode.
+    pub mode: Option<GameMode>,
+}
+
+pub async fn sessions(
+    Extension(uib_router): Extension<UiBackendRouter>,
+) -> Result<Json<Vec<SessionSummary>>, (StatusCode, String)> {
+    query_history(&uib_router, |history| history.sessions())
+        .await
+        .map(Json)
+}
+
+pub async fn session(
+    Extension(uib_router): Extension<UiBackendRouter>,
+    Path(id): Path<i64>,
+) -> Result<Json<SessionDetails>, (StatusCode, String)> {
+    match query_history(&uib_router, move |history| history.session(id)).await? {
+        Some(session) => Ok(Json(session)),
+        None => Err((StatusCode::NOT_FOUND, format!("no session {id}"))),
+    }
+}
+
+pub async fn leaderboard(
+    Extension(uib_router): Extension<UiBackendRouter>,
+    Query(query): Query<LeaderboardQuery>,
+) -> Result<Json<Vec<LeaderboardEntry>>, (StatusCode, String)> {
+    query_history(&uib_router, move |history| history.leaderboard(query.mode))
+        .await
+        .map(Json)
+}
+
+/// Run a blocking query on the history, if the server keeps one.
+async fn query_history<T, F>(
+    uib_router: &UiBackendRouter,
+    query: F,
+) -> Result<T, (StatusCode, String)>
+where
+    T: Send + 'static,
+    F: FnOnce(&History) -> rusqlite::Result<T> + Send + 'static,
+{
+    let Some(history) = uib_router.history.clone() else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "history is disabled, start the server with --history".into(),
+        ));
+    };
+    let result = tokio::task::spawn_blocking(move || query(&history))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    result.map_err(|e| {
+        println!("Could not read game history: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    })
+}
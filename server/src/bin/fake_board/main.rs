@@ -0,0 +1,170 @@
+//! Simulated buzzer board for developing without hardware.
+//!
+//! Connects to the board port of the server and speaks the same protocol as the firmware. Keys
+//! `1` to `7` press the buttons, `q` quits. The LEDs are drawn in the terminal.
+//!
+//! With `--scenario`, many boards play a scenario file instead, see [`scenario`].
+//!
+//! ```sh
+//! cargo run --bin fake_board -- [--server ADDR] [--board-id ID] [--scenario FILE]
+//! ```
+use std::{
+    io::{self, Write},
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    path::PathBuf,
+    process::ExitCode,
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use common::{buttons::DEFAULT_DEBOUNCE_US, SERVER_ADDR};
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    queue,
+    style::Print,
+    terminal::{self, ClearType},
+};
+use server::sim::{Leds, SimBoard, SimInput, SimObserver, NUM_BUTTONS, NUM_LEDS};
+use tokio::sync::mpsc;
+
+mod scenario;
+
+#[derive(Parser)]
+struct Args {
+    /// Board port of the server.
+    #[arg(long, default_value_t = default_server())]
+    server: SocketAddr,
+    /// Board ID, defaults to the process ID to keep several simulators apart.
+    #[arg(long)]
+    board_id: Option<u32>,
+    /// Play a scenario file with many boards instead of reading keys.
+    #[arg(long)]
+    scenario: Option<PathBuf>,
+    /// Websocket of the server, checked for the events of a scenario.
+    #[arg(long, default_value = "ws://127.0.0.1:3000/ws?role=display")]
+    websocket: String,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+
+    if let Some(path) = args.scenario {
+        return match scenario::run(&path, args.server, &args.websocket).await {
+            Ok(true) => ExitCode::SUCCESS,
+            Ok(false) => ExitCode::FAILURE,
+            Err(e) => {
+                println!("Scenario failed: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let board_id = args.board_id.unwrap_or_else(std::process::id);
+    let mut board = SimBoard::new(board_id);
+    let (input_tx, mut input_rx) = mpsc::unbounded_channel();
+    thread::spawn(move || read_keys(input_tx));
+
+    terminal::enable_raw_mode().expect("failed to switch terminal to raw mode");
+    let mut terminal = Terminal::default();
+    terminal.log(&format!(
+        "Fake board {board_id} with {NUM_BUTTONS} buttons, press 1-{NUM_BUTTONS} or q to quit"
+    ));
+    board.run(args.server, &mut input_rx, &mut terminal).await;
+
+    terminal::disable_raw_mode().ok();
+    println!();
+    ExitCode::SUCCESS
+}
+
+fn default_server() -> SocketAddr {
+    SocketAddrV4::new(Ipv4Addr::from(SERVER_ADDR), 8000).into()
+}
+
+/// Forward key presses from the blocking terminal, until the user quits.
+fn read_keys(input_tx: mpsc::UnboundedSender<SimInput>) {
+    // Key repeats are debounced like flanks.
+    let mut last_press: [Option<Instant>; NUM_BUTTONS] = [None; NUM_BUTTONS];
+    let debounce = Duration::from_micros(DEFAULT_DEBOUNCE_US as u64);
+
+    loop {
+        let Ok(Event::Key(key)) = event::read() else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let button_id = match key.code {
+            KeyCode::Char('q') => return,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return,
+            KeyCode::Char(c @ '1'..='7') => c as u8 - b'1',
+            _ => continue,
+        };
+
+        let now = Instant::now();
+        let last = &mut last_press[button_id as usize];
+        if last.is_some_and(|last| now - last <= debounce) {
+            continue;
+        }
+        *last = Some(now);
+        if input_tx.send(SimInput::Press(button_id)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Log lines scrolling above a line with the LEDs.
+struct Terminal {
+    leds: String,
+}
+
+impl Default for Terminal {
+    fn default() -> Self {
+        Self {
+            leds: render_leds(&Leds::default()),
+        }
+    }
+}
+
+impl Terminal {
+    fn render(&self) {
+        let mut stdout = io::stdout();
+        queue!(
+            stdout,
+            cursor::MoveToColumn(0),
+            terminal::Clear(ClearType::CurrentLine),
+            Print(&self.leds),
+        )
+        .ok();
+        stdout.flush().ok();
+    }
+}
+
+impl SimObserver for Terminal {
+    fn log(&mut self, msg: &str) {
+        let mut stdout = io::stdout();
+        queue!(
+            stdout,
+            cursor::MoveToColumn(0),
+            terminal::Clear(ClearType::CurrentLine),
+            Print(msg),
+            Print("\r\n"),
+        )
+        .ok();
+        self.render();
+    }
+
+    fn leds_changed(&mut self, leds: &Leds) {
+        self.leds = render_leds(leds);
+        self.render();
+    }
+}
+
+fn render_leds(leds: &Leds) -> String {
+    let leds: Vec<_> = (0..NUM_LEDS)
+        .map(|idx| format!("{}:{}", idx + 1, if leds.is_on(idx) { "●" } else { "○" }))
+        .collect();
+    format!("LEDs {}", leds.join(" "))
+}
@@ -0,0 +1,298 @@
+//! Scripted load with many simulated boards, checking what reaches the websocket clients.
+//!
+//! A scenario is a TOML file:
+//!
+//! ```toml
+//! boards = 8
+//! jitter_us = 2000
+//!
+//! [[step]]
+//! at_ms = 500
+//! action = "press"
+//! buttons = [0, 3]
+//!
+//! [[step]]
+//! at_ms = 1000
+//! action = "burst"
+//! buttons = [0, 1, 2, 3, 4, 5, 6]
+//! count = 20
+//! interval_us = 1000
+//!
+//! [[step]]
+//! at_ms = 2000
+//! action = "disconnect"
+//! boards = [2]
+//! for_ms = 500
+//! ```
+//!
+//! Every press must reach the websocket exactly once and in order per board.
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures_util::StreamExt;
+use rand::{rngs::StdRng, RngExt, SeedableRng};
+use serde::Deserialize;
+use server::sim::{SimBoard, SimInput, SimObserver, NUM_BUTTONS};
+use tokio::{
+    sync::mpsc,
+    time::{sleep, sleep_until, timeout, Instant},
+};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+/// Time to wait for all boards to connect.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Scenario {
+    /// Number of simulated boards.
+    boards: u32,
+    /// ID of the first board, the others count up from it.
+    #[serde(default = "default_first_board_id")]
+    first_board_id: u32,
+    /// Presses are moved by a random time of up to this many microseconds in both directions.
+    #[serde(default)]
+    jitter_us: u64,
+    /// Seed of the jitter, to replay a scenario exactly.
+    #[serde(default)]
+    seed: u64,
+    /// Time to wait for outstanding events after the last step.
+    #[serde(default = "default_settle_ms")]
+    settle_ms: u64,
+    #[serde(default, rename = "step")]
+    steps: Vec<Step>,
+}
+
+fn default_first_board_id() -> u32 {
+    1000
+}
+
+fn default_settle_ms() -> u64 {
+    2000
+}
+
+#[derive(Deserialize, Debug)]
+struct Step {
+    /// Start of the step in milliseconds after all boards connected.
+    at_ms: u64,
+    /// Indices of the boards taking part, all if empty.
+    #[serde(default)]
+    boards: Vec<u32>,
+    #[serde(flatten)]
+    action: Action,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum Action {
+    /// Press each button once.
+    Press { buttons: Vec<u8> },
+    /// Press each button `count` times, `interval_us` apart.
+    Burst {
+        buttons: Vec<u8>,
+        count: u32,
+        interval_us: u64,
+    },
+    /// Drop the connection and reconnect after `for_ms`.
+    Disconnect { for_ms: u64 },
+}
+
+impl Scenario {
+    /// Check the button IDs, which the simulated boards would otherwise report as lost presses.
+    fn validate(&self) -> Result<(), String> {
+        for (idx, step) in self.steps.iter().enumerate() {
+            let buttons = match &step.action {
+                Action::Press { buttons } | Action::Burst { buttons, .. } => buttons,
+                Action::Disconnect { .. } => continue,
+            };
+            if let Some(button_id) = buttons.iter().find(|id| **id as usize >= NUM_BUTTONS) {
+                return Err(format!(
+                    "step {} at {} ms: button {button_id} does not exist, boards have buttons 0 \
+                    to {}",
+                    idx + 1,
+                    step.at_ms,
+                    NUM_BUTTONS - 1
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Inputs of one board with their time in microseconds after the start, sorted by time.
+    fn inputs(&self, board: u32, rng: &mut StdRng) -> Vec<(u64, SimInput)> {
+        let mut inputs = Vec::new();
+        let steps = self
+            .steps
+            .iter()
+            .filter(|step| step.boards.is_empty() || step.boards.contains(&board));
+        for step in steps {
+            let at_us = step.at_ms * 1000;
+            let mut press = |time_us: u64, button_id: u8| {
+                let jitter = rng.random_range(0..=2 * self.jitter_us);
+                let time_us = (time_us + jitter).saturating_sub(self.jitter_us);
+                inputs.push((time_us, SimInput::Press(button_id)));
+            };
+            match &step.action {
+                Action::Press { buttons } => {
+                    for button_id in buttons {
+                        press(at_us, *button_id);
+                    }
+                }
+                Action::Burst {
+                    buttons,
+                    count,
+                    interval_us,
+                } => {
+                    for idx in 0..*count as u64 {
+                        for button_id in buttons {
+                            press(at_us + idx * interval_us, *button_id);
+                        }
+                    }
+                }
+                Action::Disconnect { for_ms } => {
+                    let offline = Duration::from_millis(*for_ms);
+                    inputs.push((at_us, SimInput::Disconnect(offline)));
+                }
+            }
+        }
+        inputs.sort_by_key(|(time_us, _)| *time_us);
+        inputs
+    }
+}
+
+/// Events seen on the websocket.
+#[derive(Default)]
+struct Received {
+    connected: HashSet<u32>,
+    /// Sequence numbers of the presses per board ID, in the order of arrival.
+    presses: HashMap<u32, Vec<u32>>,
+}
+
+/// Board without output, scenarios only report the result.
+struct Quiet;
+
+impl SimObserver for Quiet {}
+
+/// Play a scenario and return whether all presses reached the websocket exactly once.
+pub async fn run(
+    path: &Path,
+    server_addr: SocketAddr,
+    websocket: &str,
+) -> Result<bool, Box<dyn Error>> {
+    let scenario: Scenario = toml::from_str(&fs::read_to_string(path)?)?;
+    scenario.validate()?;
+    let board_ids: Vec<u32> = (0..scenario.boards)
+        .map(|idx| scenario.first_board_id + idx)
+        .collect();
+
+    let (ws, _) = connect_async(websocket).await?;
+    let received = Arc::new(Mutex::new(Received::default()));
+    let received_ = received.clone();
+    let collector = tokio::spawn(async move {
+        let (_, mut ws_rx) = ws.split();
+        while let Some(Ok(Message::Text(msg))) = ws_rx.next().await {
+            collect(&msg, &mut received_.lock().unwrap());
+        }
+    });
+
+    println!("Connecting {} boards to {server_addr}", board_ids.len());
+    let mut input_txs = Vec::new();
+    let mut boards = Vec::new();
+    for board_id in &board_ids {
+        let (input_tx, mut input_rx) = mpsc::unbounded_channel();
+        let mut board = SimBoard::new(*board_id);
+        input_txs.push(input_tx);
+        boards.push(tokio::spawn(async move {
+            board.run(server_addr, &mut input_rx, &mut Quiet).await;
+            board
+        }));
+    }
+
+    let all_connected = async {
+        while received.lock().unwrap().connected.len() < board_ids.len() {
+            sleep(Duration::from_millis(50)).await;
+        }
+    };
+    if timeout(CONNECT_TIMEOUT, all_connected).await.is_err() {
+        return Err("not all boards connected".into());
+    }
+
+    println!("Playing {} steps", scenario.steps.len());
+    let start = Instant::now();
+    let mut rng = StdRng::seed_from_u64(scenario.seed);
+    let mut players = Vec::new();
+    for (board, input_tx) in input_txs.into_iter().enumerate() {
+        let inputs = scenario.inputs(board as u32, &mut rng);
+        players.push(tokio::spawn(async move {
+            for (time_us, input) in inputs {
+                sleep_until(start + Duration::from_micros(time_us)).await;
+                input_tx.send(input).ok();
+            }
+            // Keep the board running until the outstanding events settled.
+            input_tx
+        }));
+    }
+    let mut input_txs = Vec::new();
+    for player in players {
+        input_txs.push(player.await?);
+    }
+    sleep(Duration::from_millis(scenario.settle_ms)).await;
+
+    // Closing the inputs stops the boards.
+    drop(input_txs);
+    let mut sent = HashMap::new();
+    for board in boards {
+        let board = board.await?;
+        sent.insert(board.board_id(), board.presses_sent());
+    }
+    collector.abort();
+
+    let received = received.lock().unwrap();
+    Ok(check(&board_ids, &sent, &received.presses))
+}
+
+fn collect(msg: &str, received: &mut Received) {
+    let Ok(event) = serde_json::from_str::<serde_json::Value>(msg) else {
+        return;
+    };
+    if let Some(board_id) = event["BoardConnected"]["board_id"].as_u64() {
+        received.connected.insert(board_id as u32);
+    }
+    let press = &event["ButtonPress"];
+    if let (Some(board_id), Some(seq)) = (press["board_id"].as_u64(), press["seq"].as_u64()) {
+        received
+            .presses
+            .entry(board_id as u32)
+            .or_default()
+            .push(seq as u32);
+    }
+}
+
+/// Compare the presses sent by every board with the ones received, printing a line per board.
+fn check(board_ids: &[u32], sent: &HashMap<u32, u32>, received: &HashMap<u32, Vec<u32>>) -> bool {
+    let mut ok = true;
+    println!("board  sent  received  lost  duplicated  reordered");
+    for board_id in board_ids {
+        let sent = sent[board_id];
+        let received = received.get(board_id).map_or(&[][..], Vec::as_slice);
+
+        let unique: HashSet<_> = received.iter().collect();
+        let lost = (1..=sent).filter(|seq| !unique.contains(seq)).count();
+        let duplicated = received.len() - unique.len();
+        let reordered = received.windows(2).filter(|pair| pair[1] < pair[0]).count();
+        println!(
+            "{board_id:>5}  {sent:>4}  {:>8}  {lost:>4}  {duplicated:>10}  {reordered:>9}",
+            received.len()
+        );
+        ok &= lost == 0 && duplicated == 0 && reordered == 0;
+    }
+    println!("{}", if ok { "OK" } else { "FAILED" });
+    ok
+}
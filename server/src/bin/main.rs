@@ -1,55 +1,207 @@
-use std::{
-    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
-    sync::Arc,
-};
+use std::{net::SocketAddr, path::PathBuf, process::ExitCode, sync::Arc};
 
 use axum::{routing::get, Extension, Router};
-use common::SERVER_ADDR;
-use server::{net_sockets::board_connection, websocket::ws_handler, UiBackendRouterInner};
-use tokio::{net::TcpListener, sync::broadcast};
+use clap::Parser;
+use server::{
+    api,
+    capture::{self, Capture},
+    config::Config,
+    game,
+    history::History,
+    net_sockets::board_connection,
+    players::PlayerRegistry,
+    websocket::ws_handler,
+    UiBackendRouter, UiBackendRouterInner,
+};
+use tokio::net::TcpListener;
 use tower_http::services::{ServeDir, ServeFile};
 
+#[derive(Parser)]
+struct Args {
+    /// Settings file, see `src/config.rs` for the format. Flags take precedence.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Address of the listener for the boards [default: 192.168.100.1:8000].
+    #[arg(long)]
+    board_listen: Option<SocketAddr>,
+    /// Address of the HTTP server [default: 127.0.0.1:3000].
+    #[arg(long)]
+    http_listen: Option<SocketAddr>,
+    /// Directory of the HTML pages and their assets [default: assets].
+    #[arg(long)]
+    assets_dir: Option<PathBuf>,
+    /// PIN of the websocket clients which control the game [default: random].
+    #[arg(long)]
+    host_pin: Option<String>,
+    /// Capacity of the event channel to the websocket clients [default: 1024].
+    #[arg(long)]
+    event_capacity: Option<usize>,
+    /// Capacity of the command channel to the boards [default: 1024].
+    #[arg(long)]
+    command_capacity: Option<usize>,
+    /// Interval of the heartbeats to and from the boards in milliseconds [default: 1000].
+    #[arg(long)]
+    heartbeat_ms: Option<u32>,
+    /// Time without any message after which a board connection is dropped [default: 3000].
+    #[arg(long)]
+    link_timeout_ms: Option<u32>,
+    /// Record all messages to and from the boards in this file.
+    #[arg(long)]
+    capture: Option<PathBuf>,
+    /// Replay a capture instead of accepting boards.
+    #[arg(long)]
+    replay: Option<PathBuf>,
+    /// Speed-up of the replay.
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+    /// Names, colors and sounds of the players, created when saved from `/api/players`.
+    #[arg(long)]
+    players: Option<PathBuf>,
+    /// Store sessions, rounds, presses and scores in this SQLite file.
+    #[arg(long)]
+    history: Option<PathBuf>,
+}
+
+impl Args {
+    /// Settings of the file, if any, overridden by the flags.
+    fn config(&self) -> Result<Config, String> {
+        let mut config = match &self.config {
+            Some(path) => Config::load(path)
+                .map_err(|e| format!("cannot read config file {}: {e}", path.display()))?,
+            None => Config::default(),
+        };
+        if let Some(addr) = self.board_listen {
+            config.board_listen = addr;
+        }
+        if let Some(addr) = self.http_listen {
+            config.http_listen = addr;
+        }
+        if let Some(dir) = &self.assets_dir {
+            config.assets_dir = dir.clone();
+        }
+        if let Some(pin) = &self.host_pin {
+            config.host_pin = Some(pin.clone());
+        }
+        if let Some(capacity) = self.event_capacity {
+            config.channels.events = capacity;
+        }
+        if let Some(capacity) = self.command_capacity {
+            config.channels.commands = capacity;
+        }
+        if let Some(interval) = self.heartbeat_ms {
+            config.link.heartbeat_ms = interval;
+        }
+        if let Some(timeout) = self.link_timeout_ms {
+            config.link.timeout_ms = timeout;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+}
+
 #[tokio::main]
-async fn main() {
-    let (frontend_tx, frontend_rx) = broadcast::channel(10);
-    let (board_tx, board_rx) = broadcast::channel(10);
-    let uib_router = Arc::new(UiBackendRouterInner {
-        frontend_tx,
-        frontend_rx,
-        board_tx,
-        board_rx,
+async fn main() -> ExitCode {
+    match run(Args::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            println!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<(), String> {
+    let config = args.config()?;
+
+    let capture = match &args.capture {
+        Some(path) => Some(
+            Capture::create(path)
+                .map_err(|e| format!("cannot create capture file {}: {e}", path.display()))?,
+        ),
+        None => None,
+    };
+    let history = match &args.history {
+        Some(path) => Some(
+            History::open(path)
+                .map_err(|e| format!("cannot open history {}: {e}", path.display()))?,
+        ),
+        None => None,
+    };
+    let players = match &args.players {
+        Some(path) => PlayerRegistry::load(path)
+            .map_err(|e| format!("cannot read players file {}: {e}", path.display()))?,
+        None => PlayerRegistry::default(),
+    };
+    let host_pin = config.host_pin.clone().unwrap_or_else(|| {
+        let pin = format!("{:06}", rand::random_range(0..1_000_000));
+        println!("Host PIN: {pin}");
+        pin
     });
+    let uib_router = Arc::new(UiBackendRouterInner::new(
+        config.link.into(),
+        config.channels,
+        capture,
+        history,
+        players,
+        host_pin,
+    ));
 
-    let server_ip = Ipv4Addr::from(SERVER_ADDR);
+    tokio::spawn(game::run(uib_router.clone()));
 
     // Open sockets
-    let uib_router_ = uib_router.clone();
-    tokio::spawn(async move {
-        let uib_router_ = uib_router_.clone();
-        let listener = TcpListener::bind(SocketAddrV4::new(server_ip, 8000))
+    if let Some(path) = &args.replay {
+        let records = capture::read_capture(path)
+            .map_err(|e| format!("cannot read capture file {}: {e}", path.display()))?;
+        println!(
+            "Replaying {} records at {}x speed",
+            records.len(),
+            args.speed
+        );
+        let uib_router_ = uib_router.clone();
+        tokio::spawn(async move {
+            capture::replay(records, args.speed, uib_router_).await;
+            println!("Replay finished");
+        });
+    } else {
+        let listener = TcpListener::bind(config.board_listen)
             .await
-            .unwrap();
-
-        loop {
-            let (socket, _) = listener.accept().await.unwrap();
-            tokio::spawn(board_connection(socket, uib_router_.clone()));
-        }
-    });
+            .map_err(|e| format!("cannot listen for boards on {}: {e}", config.board_listen))?;
+        println!("Listening for boards on {}", config.board_listen);
+        tokio::spawn(accept_boards(listener, uib_router.clone()));
+    }
 
+    let assets = &config.assets_dir;
     let app = Router::new()
         .route("/", get(root))
         .route("/ws", get(ws_handler))
-        .nest_service("/reaction", ServeFile::new("assets/reaction.html"))
-        .nest_service("/quiz", ServeFile::new("assets/quiz.html"))
-        .nest_service("/assets", ServeDir::new("assets"))
+        .route("/api/boards", get(api::boards))
+        .route("/api/players", get(api::players).put(api::set_players))
+        .route("/api/sessions", get(api::sessions))
+        .route("/api/sessions/:id", get(api::session))
+        .route("/api/leaderboard", get(api::leaderboard))
+        .nest_service("/reaction", ServeFile::new(assets.join("reaction.html")))
+        .nest_service("/quiz", ServeFile::new(assets.join("quiz.html")))
+        .nest_service("/assets", ServeDir::new(assets))
         .layer(Extension(uib_router));
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-
-    axum::Server::bind(&addr)
+    let server = axum::Server::try_bind(&config.http_listen)
+        .map_err(|e| format!("cannot serve HTTP on {}: {e}", config.http_listen))?;
+    println!("Serving the frontends on http://{}", config.http_listen);
+    server
         .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
-        .unwrap();
+        .map_err(|e| format!("HTTP server failed: {e}"))
+}
+
+async fn accept_boards(listener: TcpListener, uib_router: UiBackendRouter) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, _)) => {
+                tokio::spawn(board_connection(socket, uib_router.clone()));
+            }
+            Err(e) => println!("Could not accept board connection: {e}"),
+        }
+    }
 }
 
 async fn root() -> &'static str {
@@ -0,0 +1,227 @@
+//! Recording of the board traffic into capture files, and replay of captures in place of boards.
+//!
+//! A capture has one JSON record per line for every message to and from the boards.
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    net::SocketAddr,
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+use common::{encode_frame, BoardToServer, ServerToBoard, MAX_FRAME_SIZE, PROTOCOL_VERSION};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncWriteExt, DuplexStream},
+    sync::mpsc,
+    time::{sleep_until, Instant},
+};
+
+use crate::{clock_sync::now_us, net_sockets::serve_board, UiBackendRouter};
+
+/// Buffer size of the in-memory connection of a replayed board.
+const REPLAY_BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CaptureRecord {
+    /// Server time in microseconds since the UNIX epoch.
+    pub time_us: u64,
+    pub board_id: u32,
+    /// Address of the connection, which tells reconnects of a board apart.
+    pub addr: SocketAddr,
+    pub msg: CapturedMessage,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum CapturedMessage {
+    FromBoard(BoardToServer),
+    ToBoard(ServerToBoard),
+}
+
+/// Writer of a capture file, writing in the background.
+pub struct Capture {
+    tx: mpsc::UnboundedSender<CaptureRecord>,
+}
+
+impl Capture {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let writer = BufWriter::new(File::create(path)?);
+        let (tx, rx) = mpsc::unbounded_channel();
+        thread::spawn(move || write_records(writer, rx));
+        Ok(Self { tx })
+    }
+
+    pub fn record(&self, board_id: u32, addr: SocketAddr, msg: CapturedMessage) {
+        let record = CaptureRecord {
+            time_us: now_us(),
+            board_id,
+            addr,
+            msg,
+        };
+        self.tx.send(record).ok();
+    }
+}
+
+fn write_records(mut writer: BufWriter<File>, mut rx: mpsc::UnboundedReceiver<CaptureRecord>) {
+    while let Some(record) = rx.blocking_recv() {
+        let mut result = write_record(&mut writer, &record);
+        while let (Ok(()), Ok(record)) = (&result, rx.try_recv()) {
+            result = write_record(&mut writer, &record);
+        }
+        // Flush after every burst, so that the capture is complete even if the server crashes.
+        if let Err(e) = result.and_then(|()| writer.flush()) {
+            println!("Stopping capture: {e}");
+            return;
+        }
+    }
+}
+
+fn write_record(writer: &mut impl Write, record: &CaptureRecord) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, record)?;
+    writer.write_all(b"\n")
+}
+
+/// Read a capture recorded with the current protocol version.
+pub fn read_capture(path: &Path) -> io::Result<Vec<CaptureRecord>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+    for (idx, line) in reader.lines().enumerate() {
+        let invalid = |e: String| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {e}", idx + 1))
+        };
+        let record: CaptureRecord =
+            serde_json::from_str(&line?).map_err(|e| invalid(e.to_string()))?;
+        // The server would reject the boards, the messages may also mean something else.
+        if let CapturedMessage::FromBoard(BoardToServer::Hello(hello)) = &record.msg {
+            if hello.protocol_version != PROTOCOL_VERSION {
+                return Err(invalid(format!(
+                    "board {} speaks protocol version {}, this server version {PROTOCOL_VERSION}",
+                    record.board_id, hello.protocol_version
+                )));
+            }
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Mapping of capture times onto the replay.
+#[derive(Debug, Clone, Copy)]
+struct Timeline {
+    capture_start_us: u64,
+    replay_start: Instant,
+    replay_start_us: u64,
+    speed: f64,
+}
+
+impl Timeline {
+    fn elapsed(&self, time_us: u64) -> Duration {
+        let elapsed_us = time_us.saturating_sub(self.capture_start_us) as f64 / self.speed;
+        Duration::from_micros(elapsed_us as u64)
+    }
+
+    fn instant(&self, time_us: u64) -> Instant {
+        self.replay_start + self.elapsed(time_us)
+    }
+
+    fn server_us(&self, time_us: u64) -> u64 {
+        self.replay_start_us + self.elapsed(time_us).as_micros() as u64
+    }
+}
+
+/// Feed the board messages of a capture through [`serve_board`], `speed` times faster than
+/// recorded, and return once all replayed connections ended.
+///
+/// Every recorded connection is replayed as a connection of its own. What the server sends is
+/// discarded. Pongs are moved to the replay time, so that the clock estimate is reproduced at
+/// original speed.
+pub async fn replay(records: Vec<CaptureRecord>, speed: f64, uib_router: UiBackendRouter) {
+    let Some(first) = records.first() else {
+        return;
+    };
+    let timeline = Timeline {
+        capture_start_us: first.time_us,
+        replay_start: Instant::now(),
+        replay_start_us: now_us(),
+        speed,
+    };
+
+    let mut order = Vec::new();
+    let mut connections: HashMap<SocketAddr, Vec<(u64, BoardToServer)>> = HashMap::new();
+    for record in records {
+        let CapturedMessage::FromBoard(msg) = record.msg else {
+            continue;
+        };
+        let msgs = connections.entry(record.addr).or_insert_with(|| {
+            order.push(record.addr);
+            Vec::new()
+        });
+        msgs.push((record.time_us, msg));
+    }
+
+    let mut tasks = Vec::new();
+    for addr in order {
+        let msgs = connections.remove(&addr).unwrap_or_default();
+        tasks.push(tokio::spawn(replay_connection(
+            addr,
+            msgs,
+            timeline,
+            uib_router.clone(),
+        )));
+    }
+    for task in tasks {
+        task.await.ok();
+    }
+}
+
+async fn replay_connection(
+    addr: SocketAddr,
+    msgs: Vec<(u64, BoardToServer)>,
+    timeline: Timeline,
+    uib_router: UiBackendRouter,
+) {
+    // The board connects when it sent its first message, not at the start of the replay.
+    let Some(&(first_us, _)) = msgs.first() else {
+        return;
+    };
+    sleep_until(timeline.instant(first_us)).await;
+
+    let (board, server) = tokio::io::duplex(REPLAY_BUFFER_SIZE);
+    let (server_reader, server_writer) = tokio::io::split(server);
+    let session = tokio::spawn(serve_board(server_reader, server_writer, addr, uib_router));
+
+    let (mut board_reader, mut board_writer) = tokio::io::split(board);
+    // What the server sends is not needed, but has to be read so that it does not block.
+    let discard = tokio::spawn(async move {
+        let mut sink = tokio::io::sink();
+        tokio::io::copy(&mut board_reader, &mut sink).await
+    });
+
+    if let Err(e) = write_messages(&mut board_writer, msgs, timeline).await {
+        println!("Replay of board {addr} stopped: {e}");
+    }
+    // Ends the session like a board closing the connection.
+    board_writer.shutdown().await.ok();
+    session.await.ok();
+    discard.abort();
+}
+
+async fn write_messages(
+    writer: &mut tokio::io::WriteHalf<DuplexStream>,
+    msgs: Vec<(u64, BoardToServer)>,
+    timeline: Timeline,
+) -> io::Result<()> {
+    let mut frame_buf = [0u8; MAX_FRAME_SIZE];
+    for (time_us, mut msg) in msgs {
+        sleep_until(timeline.instant(time_us)).await;
+        if let BoardToServer::Pong(pong) = &mut msg {
+            pong.server_tx_us = timeline.server_us(pong.server_tx_us);
+        }
+        writer
+            .write_all(encode_frame(&msg, &mut frame_buf).unwrap())
+            .await?;
+    }
+    Ok(())
+}
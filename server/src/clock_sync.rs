@@ -0,0 +1,151 @@
+//! Estimation of the board clock from NTP-style ping exchanges.
+//!
+//! The offset of the board clock is measured with every [`Pong`] and tracked over time with a
+//! linear fit, so that slow drift between the two oscillators is compensated as well.
+use std::{
+    collections::VecDeque,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use common::Pong;
+use serde::Serialize;
+
+/// Number of most recent exchanges used for the estimate.
+const MAX_SAMPLES: usize = 32;
+
+/// Exchanges with a longer round trip than this multiple of the fastest one, plus
+/// [`ROUND_TRIP_SLACK_US`], are left out of the estimate.
+const MAX_ROUND_TRIP_FACTOR: f64 = 2.0;
+const ROUND_TRIP_SLACK_US: f64 = 100.0;
+
+/// Current server wall-clock time in microseconds since the UNIX epoch.
+pub fn now_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_micros() as u64)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    /// Server time in the middle of the exchange.
+    server_us: f64,
+    /// Board clock minus server clock.
+    offset_us: f64,
+    round_trip_us: f64,
+}
+
+/// Estimated relation between board clock and server clock.
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct ClockEstimate {
+    /// Board clock minus server clock at `reference_us`.
+    pub offset_us: f64,
+    /// Server time at which `offset_us` applies.
+    pub reference_us: f64,
+    /// Change of the offset per second, in microseconds.
+    pub drift_us_per_s: f64,
+    /// Bound for the error of mapped timestamps.
+    pub error_us: f64,
+    /// Exchanges the estimate is based on.
+    pub samples: usize,
+}
+
+/// Board time mapped onto the server clock.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MappedTime {
+    /// Server wall-clock time in microseconds since the UNIX epoch.
+    pub server_time_us: u64,
+    pub error_us: u64,
+}
+
+#[derive(Default)]
+pub struct ClockSync {
+    samples: VecDeque<Sample>,
+}
+
+impl ClockSync {
+    /// Add the result of an exchange, with `server_rx_us` the server time when receiving `pong`.
+    pub fn add(&mut self, pong: &Pong, server_rx_us: u64) {
+        let t1 = pong.server_tx_us as f64;
+        let t2 = pong.board_rx_us as f64;
+        let t3 = pong.board_tx_us as f64;
+        let t4 = server_rx_us as f64;
+
+        let round_trip_us = (t4 - t1) - (t3 - t2);
+        if round_trip_us < 0.0 {
+            println!("Ignoring pong with negative round trip: {pong:?}");
+            return;
+        }
+
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample {
+            server_us: (t1 + t4) / 2.0,
+            offset_us: ((t2 - t1) + (t3 - t4)) / 2.0,
+            round_trip_us,
+        });
+    }
+
+    pub fn estimate(&self) -> Option<ClockEstimate> {
+        // Slow exchanges were queued on the way, which skews their offset by up to half the delay.
+        let min_round_trip = self
+            .samples
+            .iter()
+            .map(|s| s.round_trip_us)
+            .fold(f64::INFINITY, f64::min);
+        let max_round_trip = min_round_trip * MAX_ROUND_TRIP_FACTOR + ROUND_TRIP_SLACK_US;
+        let samples: Vec<_> = self
+            .samples
+            .iter()
+            .filter(|s| s.round_trip_us <= max_round_trip)
+            .collect();
+        let last = samples.last()?;
+        let n = samples.len() as f64;
+
+        // Least-squares fit of the offset over server time, relative to the last sample.
+        let mean_t = samples
+            .iter()
+            .map(|s| s.server_us - last.server_us)
+            .sum::<f64>()
+            / n;
+        let mean_offset = samples.iter().map(|s| s.offset_us).sum::<f64>() / n;
+        let (cov, var) = samples.iter().fold((0.0, 0.0), |(cov, var), s| {
+            let dt = s.server_us - last.server_us - mean_t;
+            (cov + dt * (s.offset_us - mean_offset), var + dt * dt)
+        });
+        let slope = if var > 0.0 { cov / var } else { 0.0 };
+        let offset_us = mean_offset - slope * mean_t;
+
+        // A single offset is off by at most half its round trip, the fit adds its residuals.
+        let max_residual = samples
+            .iter()
+            .map(|s| {
+                let fitted = offset_us + slope * (s.server_us - last.server_us);
+                (s.offset_us - fitted).abs()
+            })
+            .fold(0.0, f64::max);
+
+        Some(ClockEstimate {
+            offset_us,
+            reference_us: last.server_us,
+            drift_us_per_s: slope * 1e6,
+            error_us: min_round_trip / 2.0 + max_residual,
+            samples: samples.len(),
+        })
+    }
+
+    /// Map a board clock time onto the server clock.
+    pub fn to_server_time(&self, board_us: u64) -> Option<MappedTime> {
+        let estimate = self.estimate()?;
+        let slope = estimate.drift_us_per_s / 1e6;
+
+        // Offset at the server time approximated with the offset at the reference.
+        let approx_server_us = board_us as f64 - estimate.offset_us;
+        let offset_us = estimate.offset_us + slope * (approx_server_us - estimate.reference_us);
+
+        Some(MappedTime {
+            server_time_us: (board_us as f64 - offset_us).max(0.0) as u64,
+            error_us: estimate.error_us.ceil() as u64,
+        })
+    }
+}
@@ -0,0 +1,141 @@
+//! Server settings, read from a TOML file and overridden on the command line.
+//!
+//! ```toml
+//! board_listen = "192.168.100.1:8000"
+//! http_listen = "127.0.0.1:3000"
+//! assets_dir = "assets"
+//! host_pin = "1234"
+//!
+//! [channels]
+//! events = 1024
+//! commands = 1024
+//!
+//! [link]
+//! heartbeat_ms = 1000
+//! timeout_ms = 3000
+//! ```
+//!
+//! Missing keys keep their defaults.
+use std::{
+    fs, io,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    path::{Path, PathBuf},
+};
+
+use common::{LinkTiming, SERVER_ADDR};
+use serde::Deserialize;
+
+use crate::{LinkConfig, CHANNEL_CAPACITY};
+
+/// Port on which the boards connect.
+pub const BOARD_PORT: u16 = 8000;
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address of the listener for the boards.
+    pub board_listen: SocketAddr,
+    /// Address of the HTTP server for the frontends.
+    pub http_listen: SocketAddr,
+    /// Directory of the HTML pages and their assets.
+    pub assets_dir: PathBuf,
+    /// PIN of the websocket clients which control the game, random if unset.
+    pub host_pin: Option<String>,
+    pub channels: ChannelConfig,
+    pub link: LinkSettings,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            board_listen: SocketAddrV4::new(Ipv4Addr::from(SERVER_ADDR), BOARD_PORT).into(),
+            http_listen: SocketAddr::from(([127, 0, 0, 1], 3000)),
+            assets_dir: PathBuf::from("assets"),
+            host_pin: None,
+            channels: ChannelConfig::default(),
+            link: LinkSettings::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        toml::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Check the settings which cannot be used as they are.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.channels.events == 0 || self.channels.commands == 0 {
+            return Err("channel capacities must be at least 1".into());
+        }
+        if self.link.heartbeat_ms == 0 {
+            return Err("heartbeat interval must be at least 1 ms".into());
+        }
+        if self.link.timeout_ms <= self.link.heartbeat_ms {
+            return Err(format!(
+                "link timeout of {} ms must be longer than the heartbeat interval of {} ms",
+                self.link.timeout_ms, self.link.heartbeat_ms
+            ));
+        }
+        if self.host_pin.as_deref() == Some("") {
+            return Err("host PIN must not be empty".into());
+        }
+        if !self.assets_dir.is_dir() {
+            return Err(format!(
+                "assets directory {} not found",
+                self.assets_dir.display()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Capacities of the broadcast channels, see [`CHANNEL_CAPACITY`].
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChannelConfig {
+    /// Events for the websocket clients.
+    pub events: usize,
+    /// Commands for the boards.
+    pub commands: usize,
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        Self {
+            events: CHANNEL_CAPACITY,
+            commands: CHANNEL_CAPACITY,
+        }
+    }
+}
+
+/// Liveness settings of the board connections, also sent to the boards.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinkSettings {
+    /// Interval of heartbeats in both directions.
+    pub heartbeat_ms: u32,
+    /// Time without any message after which either side drops the connection.
+    pub timeout_ms: u32,
+}
+
+impl Default for LinkSettings {
+    fn default() -> Self {
+        let timing = LinkTiming::default();
+        Self {
+            heartbeat_ms: timing.heartbeat_ms,
+            timeout_ms: timing.timeout_ms,
+        }
+    }
+}
+
+impl From<LinkSettings> for LinkConfig {
+    fn from(link: LinkSettings) -> Self {
+        LinkTiming {
+            heartbeat_ms: link.heartbeat_ms,
+            timeout_ms: link.timeout_ms,
+        }
+        .into()
+    }
+}
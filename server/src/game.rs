@@ -0,0 +1,433 @@
+//! Game rules, evaluated once on the server for all frontends.
+//!
+//! The game consumes the button presses of all boards, drives their LEDs and publishes a
+//! [`GameState`] snapshot after every change. Frontends only render the latest snapshot, so any
+//! number of them show the same game.
+//!
+//! The reaction countdown is drawn and timed here as well. At its end, all frontends receive
+//! [`UiEvent::Go`] at once, and presses are scored against the server clock. The winner is
+//! decided after [`SETTLE_WINDOW`], so that a faster press arriving later still wins.
+use std::{ops::Range, time::Duration};
+
+use common::{EffectKind, LockMode, BOARD_CLOCK_RESOLUTION_US};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::broadcast::error::RecvError,
+    time::{sleep_until, Instant},
+};
+
+use crate::{
+    clock_sync::now_us,
+    websocket::{UiButtonPress, UiCommand, UiEvent, UiLedEffect, UiLedFrame, UiLock},
+    UiBackendRouter,
+};
+
+/// Mask addressing all player slots.
+pub const ALL_SLOTS: u64 = u64::MAX;
+
+/// Range of the random reaction countdown in milliseconds.
+pub const COUNTDOWN_MS: Range<u32> = 2000..5000;
+
+/// Time after the first reaction of a round arrived during which faster ones may still arrive.
+pub const SETTLE_WINDOW: Duration = Duration::from_millis(100);
+
+/// Commands of the websocket clients controlling the game.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameCommand {
+    /// Start a reaction round with a random countdown, after which presses count.
+    StartReaction,
+    /// Start a quiz with all players.
+    StartQuiz,
+    /// Disqualify the winner of the current quiz round and start the next round.
+    ContinueQuiz,
+}
+
+/// Snapshot of the game, pushed to the websocket clients after every change.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub enum GameState {
+    #[default]
+    Idle,
+    Reaction(Round),
+    Quiz(Quiz),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameMode {
+    Reaction,
+    Quiz,
+}
+
+impl GameState {
+    pub fn mode(&self) -> Option<GameMode> {
+        match self {
+            GameState::Idle => None,
+            GameState::Reaction(_) => Some(GameMode::Reaction),
+            GameState::Quiz(_) => Some(GameMode::Quiz),
+        }
+    }
+
+    /// The round being played, if any.
+    pub fn round(&self) -> Option<&Round> {
+        match self {
+            GameState::Idle => None,
+            GameState::Reaction(round) => Some(round),
+            GameState::Quiz(quiz) => Some(&quiz.round),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Round {
+    /// Counts up with every round, so that renderers notice a new round.
+    pub number: u32,
+    /// Hidden from the players, who only learn when the countdown passed.
+    #[serde(skip)]
+    pub countdown_ms: u32,
+    /// Server time in microseconds since the UNIX epoch at which the countdown ends.
+    #[serde(skip)]
+    pub go_us: u64,
+    /// Server time at which the winner is decided, once the round is settling.
+    #[serde(skip)]
+    pub decide_us: u64,
+    /// Presses after the countdown, in the order of arrival.
+    pub reactions: Vec<Reaction>,
+    /// Presses before the countdown passed.
+    pub false_starts: Vec<Reaction>,
+    pub phase: RoundPhase,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RoundPhase {
+    /// Presses are false starts.
+    Countdown,
+    /// Waiting for the first press after the countdown.
+    #[default]
+    Open,
+    /// A reaction arrived, the winner is decided once faster ones had time to arrive.
+    Settling,
+    /// The fastest player is known and their LED flashes.
+    Won { slot: u8 },
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reaction {
+    pub slot: u8,
+    /// Time of the press relative to the end of the countdown, not positive for false starts.
+    pub reaction_us: i64,
+    /// Measured on the server clock, otherwise on the board clock relative to the game init.
+    pub server_timed: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Quiz {
+    pub round: Round,
+    /// Slots which gave a wrong answer, in order. Their buttons are locked.
+    pub disqualified: Vec<u8>,
+}
+
+impl Round {
+    /// Record the first press of a slot, arriving at server time `now_us`. Returns whether it
+    /// counted.
+    fn press(&mut self, press: &UiButtonPress, now_us: u64) -> bool {
+        let slot = press.slot;
+        let mut pressed = self.reactions.iter().chain(&self.false_starts);
+        if pressed.any(|reaction| reaction.slot == slot) {
+            return false;
+        }
+
+        let reaction = match press.server_time {
+            Some(time) => Reaction {
+                slot,
+                reaction_us: time.server_time_us as i64 - self.go_us as i64,
+                server_timed: true,
+            },
+            // The board started counting when it received the game init.
+            None => Reaction {
+                slot,
+                reaction_us: press.press.micros_since_init as i64 - self.countdown_ms as i64 * 1000,
+                server_timed: false,
+            },
+        };
+        if reaction.reaction_us <= 0 {
+            self.false_starts.push(reaction);
+            return true;
+        }
+        self.reactions.push(reaction);
+        // A press timed after the end may arrive before the go is handled.
+        if matches!(self.phase, RoundPhase::Countdown | RoundPhase::Open) {
+            self.phase = RoundPhase::Settling;
+            self.decide_us = now_us + SETTLE_WINDOW.as_micros() as u64;
+        }
+        true
+    }
+
+    /// Pick the fastest reaction as the winner, returning the commands for the boards.
+    fn decide(&mut self) -> Option<Vec<UiCommand>> {
+        if self.phase != RoundPhase::Settling {
+            return None;
+        }
+        let fastest_us = self.reactions.iter().map(|r| r.reaction_us).min()?;
+        // The board clock cannot tell closer presses apart, the earlier arrival wins the tie.
+        let slot = self
+            .reactions
+            .iter()
+            .find(|r| r.reaction_us < fastest_us + BOARD_CLOCK_RESOLUTION_US as i64)?
+            .slot;
+        self.phase = RoundPhase::Won { slot };
+        // With several boards, each one lit its own first press in a quiz.
+        let others_off = UiCommand::LedFrame(UiLedFrame {
+            mask: ALL_SLOTS & !slot_mask(slot),
+            on: 0,
+        });
+        // The board blinks the LED on its own and keeps it on afterwards.
+        let flash = UiCommand::LedEffect(UiLedEffect {
+            mask: slot_mask(slot),
+            kind: EffectKind::FlashThenHold {
+                period_ms: 200,
+                count: 3,
+            },
+        });
+        Some(vec![others_off, flash])
+    }
+}
+
+/// State machine of the game modes.
+#[derive(Debug, Default)]
+pub struct Game {
+    state: GameState,
+    /// Number of the latest round of any mode.
+    round_number: u32,
+}
+
+impl Game {
+    pub fn state(&self) -> &GameState {
+        &self.state
+    }
+
+    /// Apply a command at server time `now_us`, returning the commands for the boards or `None`
+    /// if it does not apply.
+    pub fn command(&mut self, command: GameCommand, now_us: u64) -> Option<Vec<UiCommand>> {
+        match (command, &mut self.state) {
+            (GameCommand::StartReaction, _) => {
+                let countdown_ms = rand::random_range(COUNTDOWN_MS);
+                let mut round = self.next_round(countdown_ms, now_us);
+                round.phase = RoundPhase::Countdown;
+                self.state = GameState::Reaction(round);
+                Some(vec![
+                    all_leds_off(),
+                    // A previous quiz may have left first-press-wins and locks behind.
+                    UiCommand::FirstPressWins(None),
+                    UiCommand::Arm(ALL_SLOTS),
+                    UiCommand::InitReactionGame(countdown_ms),
+                ])
+            }
+            (GameCommand::StartQuiz, _) => {
+                self.state = GameState::Quiz(Quiz {
+                    round: self.next_round(0, now_us),
+                    disqualified: Vec::new(),
+                });
+                Some(vec![
+                    all_leds_off(),
+                    UiCommand::InitReactionGame(0),
+                    // Later presses are only tagged, so that they still show up as reactions.
+                    UiCommand::FirstPressWins(Some(LockMode::Tag)),
+                    UiCommand::Arm(ALL_SLOTS),
+                ])
+            }
+            (GameCommand::ContinueQuiz, GameState::Quiz(quiz)) => {
+                let mut commands = vec![all_leds_off()];
+                if let RoundPhase::Won { slot } = quiz.round.phase {
+                    quiz.disqualified.push(slot);
+                    // Disqualified players cannot make noise anymore.
+                    commands.push(UiCommand::Lock(UiLock {
+                        mask: slot_mask(slot),
+                        mode: LockMode::Discard,
+                    }));
+                }
+                self.round_number += 1;
+                quiz.round = Round {
+                    number: self.round_number,
+                    go_us: now_us,
+                    ..Round::default()
+                };
+
+                let disqualified = quiz
+                    .disqualified
+                    .iter()
+                    .fold(0, |mask, slot| mask | slot_mask(*slot));
+                commands.push(UiCommand::InitReactionGame(0));
+                commands.push(UiCommand::Arm(ALL_SLOTS & !disqualified));
+                Some(commands)
+            }
+            (GameCommand::ContinueQuiz, _) => None,
+        }
+    }
+
+    /// Score a button press arriving at server time `now_us`, returning whether it counted.
+    pub fn press(&mut self, press: &UiButtonPress, now_us: u64) -> bool {
+        if press.press.before_game {
+            return false;
+        }
+        match &mut self.state {
+            GameState::Idle => false,
+            GameState::Reaction(round) => round.press(press, now_us),
+            GameState::Quiz(quiz) => {
+                if quiz.disqualified.contains(&press.slot) {
+                    return false;
+                }
+                quiz.round.press(press, now_us)
+            }
+        }
+    }
+
+    /// Server time at which the winner of the settling round is decided.
+    pub fn decide_us(&self) -> Option<u64> {
+        self.state
+            .round()
+            .filter(|round| round.phase == RoundPhase::Settling)
+            .map(|round| round.decide_us)
+    }
+
+    /// Decide the winner of the settling round, returning the commands for the boards.
+    pub fn decide(&mut self) -> Option<Vec<UiCommand>> {
+        match &mut self.state {
+            GameState::Idle => None,
+            GameState::Reaction(round) => round.decide(),
+            GameState::Quiz(quiz) => quiz.round.decide(),
+        }
+    }
+
+    /// Server time at which the running countdown ends.
+    pub fn go_us(&self) -> Option<u64> {
+        match &self.state {
+            GameState::Reaction(round) if round.phase == RoundPhase::Countdown => Some(round.go_us),
+            _ => None,
+        }
+    }
+
+    /// End the countdown, returning the number of the round if one was running.
+    pub fn go(&mut self) -> Option<u32> {
+        match &mut self.state {
+            GameState::Reaction(round) if round.phase == RoundPhase::Countdown => {
+                round.phase = RoundPhase::Open;
+                Some(round.number)
+            }
+            _ => None,
+        }
+    }
+
+    fn next_round(&mut self, countdown_ms: u32, now_us: u64) -> Round {
+        self.round_number += 1;
+        Round {
+            number: self.round_number,
+            countdown_ms,
+            go_us: now_us + countdown_ms as u64 * 1000,
+            ..Round::default()
+        }
+    }
+}
+
+fn slot_mask(slot: u8) -> u64 {
+    1u64.checked_shl(slot as u32).unwrap_or(0)
+}
+
+fn all_leds_off() -> UiCommand {
+    UiCommand::LedFrame(UiLedFrame {
+        mask: ALL_SLOTS,
+        on: 0,
+    })
+}
+
+/// Run the game on the presses and commands passing through the router.
+pub async fn run(uib_router: UiBackendRouter) {
+    let mut events = uib_router.frontend_rx.resubscribe();
+    let mut commands = uib_router.board_rx.resubscribe();
+    let mut game = Game::default();
+
+    loop {
+        let deadline = game.go_us().or_else(|| game.decide_us());
+        let deadline_at = deadline.map(|time_us| {
+            Instant::now() + Duration::from_micros(time_us.saturating_sub(now_us()))
+        });
+        let board_commands = tokio::select! {
+            event = events.recv() => match event {
+                Ok(UiEvent::ButtonPress(press)) => {
+                    let counted = game.press(&press, now_us());
+                    if counted {
+                        record_press(&uib_router, &press, game.state());
+                    }
+                    counted.then(Vec::new)
+                }
+                Ok(_) => None,
+                Err(RecvError::Lagged(skipped)) => {
+                    println!("Game fell behind, skipped {skipped} events");
+                    None
+                }
+                Err(RecvError::Closed) => return,
+            },
+            command = commands.recv() => match command {
+                Ok(UiCommand::Game(command)) => {
+                    let before = game.state().clone();
+                    let board_commands = game.command(command, now_us());
+                    if let (Some(_), Some(history)) = (&board_commands, &uib_router.history) {
+                        history.command(command, &before, game.state());
+                    }
+                    if let (Some(_), GameState::Reaction(round)) = (&board_commands, game.state()) {
+                        let countdown = UiEvent::Countdown {
+                            round: round.number,
+                            countdown_ms: round.countdown_ms,
+                        };
+                        uib_router.frontend_tx.send(countdown).ok();
+                    }
+                    board_commands
+                }
+                Ok(_) => None,
+                Err(RecvError::Lagged(skipped)) => {
+                    println!("Game fell behind, skipped {skipped} commands");
+                    None
+                }
+                Err(RecvError::Closed) => return,
+            },
+            _ = sleep_until(deadline_at.unwrap_or_else(Instant::now)), if deadline_at.is_some() => {
+                if let Some(round) = game.go() {
+                    uib_router.frontend_tx.send(UiEvent::Go { round }).ok();
+                    Some(Vec::new())
+                } else {
+                    let board_commands = game.decide();
+                    let winner = game.state().round().map(|round| round.phase);
+                    if let (Some(RoundPhase::Won { slot }), Some(history)) =
+                        (winner, &uib_router.history)
+                    {
+                        history.winner(slot);
+                    }
+                    board_commands
+                }
+            }
+        };
+        let Some(board_commands) = board_commands else {
+            continue;
+        };
+
+        for command in board_commands {
+            uib_router.board_tx.send(command).ok();
+        }
+        let state = game.state().clone();
+        *uib_router.game.lock().unwrap() = state.clone();
+        uib_router.frontend_tx.send(UiEvent::GameState(state)).ok();
+    }
+}
+
+/// Store a press in the history, with the current name of the player.
+fn record_press(uib_router: &UiBackendRouter, press: &UiButtonPress, state: &GameState) {
+    let Some(history) = &uib_router.history else {
+        return;
+    };
+    let players = uib_router.players.lock().unwrap();
+    let player = players
+        .lookup(press.board_id, press.press.button_id)
+        .map_or_else(
+            || format!("Slot {}", press.slot),
+            |player| player.name.clone(),
+        );
+    history.press(press, state, player);
+}
@@ -0,0 +1,483 @@
+//! History of the played sessions in an SQLite file.
+//!
+//! A session is a quiz, or a series of reaction rounds. Every press is stored with its reaction
+//! time, and players score a point for every round they win without being disqualified.
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+use crate::{
+    clock_sync::now_us,
+    game::{GameCommand, GameMode, GameState},
+    websocket::UiButtonPress,
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY,
+    mode TEXT NOT NULL,
+    started_us INTEGER NOT NULL,
+    ended_us INTEGER
+);
+CREATE TABLE IF NOT EXISTS rounds (
+    id INTEGER PRIMARY KEY,
+    session_id INTEGER NOT NULL REFERENCES sessions (id),
+    number INTEGER NOT NULL,
+    started_us INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS presses (
+    id INTEGER PRIMARY KEY,
+    round_id INTEGER NOT NULL REFERENCES rounds (id),
+    slot INTEGER NOT NULL,
+    board_id INTEGER NOT NULL,
+    button_id INTEGER NOT NULL,
+    player TEXT NOT NULL,
+    time_us INTEGER NOT NULL,
+    reaction_us INTEGER NOT NULL,
+    server_timed INTEGER NOT NULL,
+    false_start INTEGER NOT NULL,
+    winner INTEGER NOT NULL,
+    disqualified INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS scores (
+    session_id INTEGER NOT NULL REFERENCES sessions (id),
+    player TEXT NOT NULL,
+    points INTEGER NOT NULL,
+    PRIMARY KEY (session_id, player)
+);
+";
+
+/// Writer of the history, writing in the background.
+#[derive(Debug, Clone)]
+pub struct History {
+    path: PathBuf,
+    tx: mpsc::Sender<Record>,
+}
+
+/// Change of the game to be stored.
+#[derive(Debug)]
+enum Record {
+    Round {
+        mode: GameMode,
+        new_session: bool,
+        time_us: u64,
+    },
+    Press(PressRecord),
+    Winner {
+        slot: u8,
+    },
+    Disqualify {
+        slot: u8,
+    },
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SessionSummary {
+    pub id: i64,
+    pub mode: GameMode,
+    /// Server time in microseconds since the UNIX epoch.
+    pub started_us: u64,
+    /// Set once the next session started.
+    pub ended_us: Option<u64>,
+    pub num_rounds: u32,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SessionDetails {
+    #[serde(flatten)]
+    pub session: SessionSummary,
+    pub rounds: Vec<RoundRecord>,
+    /// Points per player, best first.
+    pub scores: Vec<Score>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct RoundRecord {
+    /// Number of the round within the session, starting at 1.
+    pub number: u32,
+    pub started_us: u64,
+    /// Presses in the order of arrival.
+    pub presses: Vec<PressRecord>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PressRecord {
+    pub slot: u8,
+    pub board_id: u32,
+    pub button_id: u8,
+    /// Name of the player at the time of the press.
+    pub player: String,
+    /// Server time of the press.
+    pub time_us: u64,
+    pub reaction_us: i64,
+    pub server_timed: bool,
+    pub false_start: bool,
+    pub winner: bool,
+    pub disqualified: bool,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Score {
+    pub player: String,
+    pub points: i64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct LeaderboardEntry {
+    pub player: String,
+    pub points: i64,
+    /// Sessions in which the player pressed at least once.
+    pub sessions: u32,
+}
+
+impl History {
+    /// Open or create the history file.
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        // Readers of the API do not block the writer.
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)?;
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || write_records(conn, rx));
+        Ok(Self {
+            path: path.to_owned(),
+            tx,
+        })
+    }
+
+    /// Store the effect of a game command, given the states before and after it.
+    pub fn command(&self, command: GameCommand, before: &GameState, after: &GameState) {
+        let time_us = now_us();
+        let record = match (command, before, after) {
+            (GameCommand::StartReaction, _, _) => Record::Round {
+                mode: GameMode::Reaction,
+                new_session: before.mode() != Some(GameMode::Reaction),
+                time_us,
+            },
+            (GameCommand::StartQuiz, _, _) => Record::Round {
+                mode: GameMode::Quiz,
+                new_session: true,
+                time_us,
+            },
+            (GameCommand::ContinueQuiz, GameState::Quiz(before), GameState::Quiz(after)) => {
+                if after.disqualified.len() > before.disqualified.len() {
+                    if let Some(slot) = after.disqualified.last() {
+                        self.send(Record::Disqualify { slot: *slot });
+                    }
+                }
+                Record::Round {
+                    mode: GameMode::Quiz,
+                    new_session: false,
+                    time_us,
+                }
+            }
+            _ => return,
+        };
+        self.send(record);
+    }
+
+    /// Store a press which counted, given the state after it.
+    pub fn press(&self, press: &UiButtonPress, state: &GameState, player: String) {
+        let Some(round) = state.round() else {
+            return;
+        };
+        let slot = press.slot;
+        let (reaction, false_start) = match round.reactions.iter().find(|r| r.slot == slot) {
+            Some(reaction) => (reaction, false),
+            None => match round.false_starts.iter().find(|r| r.slot == slot) {
+                Some(reaction) => (reaction, true),
+                None => return,
+            },
+        };
+        self.send(Record::Press(PressRecord {
+            slot,
+            board_id: press.board_id,
+            button_id: press.press.button_id,
+            player,
+            time_us: press.server_time.map_or_else(now_us, |t| t.server_time_us),
+            reaction_us: reaction.reaction_us,
+            server_timed: reaction.server_timed,
+            false_start,
+            // Decided later, see [`History::winner`].
+            winner: false,
+            disqualified: false,
+        }));
+    }
+
+    /// Store the winner of the current round once it is decided.
+    pub fn winner(&self, slot: u8) {
+        self.send(Record::Winner { slot });
+    }
+
+    fn send(&self, record: Record) {
+        self.tx.send(record).ok();
+    }
+
+    /// All sessions, latest first.
+    pub fn sessions(&self) -> rusqlite::Result<Vec<SessionSummary>> {
+        let conn = Connection::open(&self.path)?;
+        let mut stmt = conn.prepare(
+            "SELECT id, mode, started_us, ended_us,
+                (SELECT COUNT(*) FROM rounds WHERE session_id = sessions.id)
+            FROM sessions ORDER BY id DESC",
+        )?;
+        let sessions = stmt.query_map([], session_summary)?.collect();
+        sessions
+    }
+
+    pub fn session(&self, id: i64) -> rusqlite::Result<Option<SessionDetails>> {
+        let conn = Connection::open(&self.path)?;
+        let session = conn
+            .query_row(
+                "SELECT id, mode, started_us, ended_us,
+                    (SELECT COUNT(*) FROM rounds WHERE session_id = sessions.id)
+                FROM sessions WHERE id = ?1",
+                [id],
+                session_summary,
+            )
+            .optional()?;
+        let Some(session) = session else {
+            return Ok(None);
+        };
+
+        let mut rounds = Vec::new();
+        let mut round_stmt = conn.prepare(
+            "SELECT id, number, started_us FROM rounds WHERE session_id = ?1 ORDER BY number",
+        )?;
+        let mut press_stmt = conn.prepare(
+            "SELECT slot, board_id, button_id, player, time_us, reaction_us, server_timed,
+                false_start, winner, disqualified
+            FROM presses WHERE round_id = ?1 ORDER BY id",
+        )?;
+        let round_rows: Vec<(i64, u32, u64)> = round_stmt
+            .query_map([id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        for (round_id, number, started_us) in round_rows {
+            let presses = press_stmt
+                .query_map([round_id], |row| {
+                    Ok(PressRecord {
+                        slot: row.get(0)?,
+                        board_id: row.get(1)?,
+                        button_id: row.get(2)?,
+                        player: row.get(3)?,
+                        time_us: row.get(4)?,
+                        reaction_us: row.get(5)?,
+                        server_timed: row.get(6)?,
+                        false_start: row.get(7)?,
+                        winner: row.get(8)?,
+                        disqualified: row.get(9)?,
+                    })
+                })?
+                .collect::<rusqlite::Result<_>>()?;
+            rounds.push(RoundRecord {
+                number,
+                started_us,
+                presses,
+            });
+        }
+
+        let scores = conn
+            .prepare(
+                "SELECT player, points FROM scores WHERE session_id = ?1
+                ORDER BY points DESC, player",
+            )?
+            .query_map([id], |row| {
+                Ok(Score {
+                    player: row.get(0)?,
+                    points: row.get(1)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(Some(SessionDetails {
+            session,
+            rounds,
+            scores,
+        }))
+    }
+
+    /// Points of every player over all sessions, optionally of one mode, best first.
+    pub fn leaderboard(&self, mode: Option<GameMode>) -> rusqlite::Result<Vec<LeaderboardEntry>> {
+        let conn = Connection::open(&self.path)?;
+        let mut stmt = conn.prepare(
+            "SELECT player, SUM(points) AS total, COUNT(*) FROM scores
+            JOIN sessions ON sessions.id = scores.session_id
+            WHERE ?1 IS NULL OR sessions.mode = ?1
+            GROUP BY player ORDER BY total DESC, player",
+        )?;
+        let entries = stmt
+            .query_map([mode.map(mode_name)], |row| {
+                Ok(LeaderboardEntry {
+                    player: row.get(0)?,
+                    points: row.get(1)?,
+                    sessions: row.get(2)?,
+                })
+            })?
+            .collect();
+        entries
+    }
+}
+
+fn mode_name(mode: GameMode) -> &'static str {
+    match mode {
+        GameMode::Reaction => "Reaction",
+        GameMode::Quiz => "Quiz",
+    }
+}
+
+fn session_summary(row: &rusqlite::Row) -> rusqlite::Result<SessionSummary> {
+    let mode: String = row.get(1)?;
+    let mode = match mode.as_str() {
+        "Reaction" => GameMode::Reaction,
+        "Quiz" => GameMode::Quiz,
+        _ => {
+            let e = format!("unknown game mode {mode}").into();
+            return Err(rusqlite::Error::FromSqlConversionFailure(
+                1,
+                rusqlite::types::Type::Text,
+                e,
+            ));
+        }
+    };
+    Ok(SessionSummary {
+        id: row.get(0)?,
+        mode,
+        started_us: row.get(2)?,
+        ended_us: row.get(3)?,
+        num_rounds: row.get(4)?,
+    })
+}
+
+/// Position of the writer in the history.
+#[derive(Default)]
+struct Cursor {
+    session: Option<(i64, GameMode)>,
+    round_id: Option<i64>,
+    round_number: u32,
+}
+
+fn write_records(conn: Connection, rx: mpsc::Receiver<Record>) {
+    let mut cursor = Cursor::default();
+    for record in rx {
+        if let Err(e) = write_record(&conn, &mut cursor, record) {
+            println!("Could not store game history: {e}");
+        }
+    }
+}
+
+fn write_record(conn: &Connection, cursor: &mut Cursor, record: Record) -> rusqlite::Result<()> {
+    match record {
+        Record::Round {
+            mode,
+            new_session,
+            time_us,
+        } => {
+            let session_id = match cursor.session {
+                Some((id, current)) if !new_session && current == mode => id,
+                previous => {
+                    if let Some((id, _)) = previous {
+                        conn.execute(
+                            "UPDATE sessions SET ended_us = ?2 WHERE id = ?1",
+                            params![id, time_us],
+                        )?;
+                    }
+                    conn.execute(
+                        "INSERT INTO sessions (mode, started_us) VALUES (?1, ?2)",
+                        params![mode_name(mode), time_us],
+                    )?;
+                    let id = conn.last_insert_rowid();
+                    *cursor = Cursor {
+                        session: Some((id, mode)),
+                        ..Cursor::default()
+                    };
+                    id
+                }
+            };
+            cursor.round_number += 1;
+            conn.execute(
+                "INSERT INTO rounds (session_id, number, started_us) VALUES (?1, ?2, ?3)",
+                params![session_id, cursor.round_number, time_us],
+            )?;
+            cursor.round_id = Some(conn.last_insert_rowid());
+        }
+        Record::Press(press) => {
+            let (Some((session_id, _)), Some(round_id)) = (cursor.session, cursor.round_id) else {
+                return Ok(());
+            };
+            conn.execute(
+                "INSERT INTO presses (round_id, slot, board_id, button_id, player, time_us,
+                    reaction_us, server_timed, false_start, winner)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                params![
+                    round_id,
+                    press.slot,
+                    press.board_id,
+                    press.button_id,
+                    press.player,
+                    press.time_us,
+                    press.reaction_us,
+                    press.server_timed,
+                    press.false_start,
+                    press.winner,
+                ],
+            )?;
+            // Every player who pressed shows up in the scores.
+            conn.execute(
+                "INSERT INTO scores (session_id, player, points) VALUES (?1, ?2, 0)
+                ON CONFLICT (session_id, player) DO NOTHING",
+                params![session_id, press.player],
+            )?;
+        }
+        Record::Winner { slot } => {
+            let (Some((session_id, _)), Some(round_id)) = (cursor.session, cursor.round_id) else {
+                return Ok(());
+            };
+            let player: Option<String> = conn
+                .query_row(
+                    "SELECT player FROM presses WHERE round_id = ?1 AND slot = ?2",
+                    params![round_id, slot],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            let Some(player) = player else {
+                return Ok(());
+            };
+            conn.execute(
+                "UPDATE presses SET winner = 1 WHERE round_id = ?1 AND slot = ?2",
+                params![round_id, slot],
+            )?;
+            conn.execute(
+                "UPDATE scores SET points = points + 1 WHERE session_id = ?1 AND player = ?2",
+                params![session_id, player],
+            )?;
+        }
+        Record::Disqualify { slot } => {
+            let (Some((session_id, _)), Some(round_id)) = (cursor.session, cursor.round_id) else {
+                return Ok(());
+            };
+            let press: Option<(String, bool)> = conn
+                .query_row(
+                    "SELECT player, winner FROM presses WHERE round_id = ?1 AND slot = ?2",
+                    params![round_id, slot],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?;
+            conn.execute(
+                "UPDATE presses SET disqualified = 1 WHERE round_id = ?1 AND slot = ?2",
+                params![round_id, slot],
+            )?;
+            if let Some((player, true)) = press {
+                conn.execute(
+                    "UPDATE scores SET points = points - 1 WHERE session_id = ?1 AND player = ?2",
+                    params![session_id, player],
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,87 @@
+//! Check of the host PIN, which guards everything that changes the game.
+//!
+//! After [`MAX_FAILED_ATTEMPTS`] wrong PINs in a row, every attempt is rejected for [`LOCKOUT`],
+//! so that the PIN cannot be guessed by trying all of them.
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::http::StatusCode;
+
+/// Wrong PINs in a row after which attempts are locked out.
+pub const MAX_FAILED_ATTEMPTS: u32 = 5;
+
+/// Time during which all attempts are rejected after too many wrong PINs.
+pub const LOCKOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub struct HostPin {
+    pin: String,
+    attempts: Mutex<Attempts>,
+}
+
+#[derive(Debug, Default)]
+struct Attempts {
+    /// Wrong PINs since the last correct one or lockout.
+    failed: u32,
+    locked_until: Option<Instant>,
+}
+
+/// Why a PIN was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinError {
+    Wrong,
+    LockedOut,
+}
+
+impl PinError {
+    pub fn status(self) -> (StatusCode, &'static str) {
+        match self {
+            PinError::Wrong => (StatusCode::UNAUTHORIZED, "wrong host PIN"),
+            PinError::LockedOut => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "too many wrong host PINs, try again later",
+            ),
+        }
+    }
+}
+
+impl HostPin {
+    pub fn new(pin: String) -> Self {
+        Self {
+            pin,
+            attempts: Mutex::new(Attempts::default()),
+        }
+    }
+
+    /// Check a PIN given by a client, `None` if it gave none.
+    pub fn check(&self, pin: Option<&str>) -> Result<(), PinError> {
+        let mut attempts = self.attempts.lock().unwrap();
+        let now = Instant::now();
+        if attempts.locked_until.is_some_and(|until| now < until) {
+            return Err(PinError::LockedOut);
+        }
+        if pin.is_some_and(|pin| constant_time_eq(pin.as_bytes(), self.pin.as_bytes())) {
+            *attempts = Attempts::default();
+            return Ok(());
+        }
+        attempts.failed += 1;
+        if attempts.failed >= MAX_FAILED_ATTEMPTS {
+            attempts.failed = 0;
+            attempts.locked_until = Some(now + LOCKOUT);
+        }
+        Err(PinError::Wrong)
+    }
+}
+
+/// Compare without returning early, so that the time taken does not tell how much of a guess
+/// was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let len = a.len().max(b.len());
+    let diff = (0..len).fold(a.len() ^ b.len(), |diff, idx| {
+        let (x, y) = (a.get(idx).copied(), b.get(idx).copied());
+        diff | (x.unwrap_or(0) ^ y.unwrap_or(0)) as usize
+    });
+    diff == 0
+}
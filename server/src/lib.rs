@@ -1,16 +1,164 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use common::Message;
+use capture::Capture;
+use clock_sync::ClockEstimate;
+use common::{BoardStatus, ButtonConfig, FrameStats, Hello, LinkTiming};
+use config::ChannelConfig;
+use game::GameState;
+use history::History;
+use host_pin::HostPin;
+use players::PlayerRegistry;
+use serde::Serialize;
+use slots::{PlayerSlots, SlotRange};
 use tokio::sync::broadcast;
+use websocket::{UiCommand, UiEvent};
 
+pub mod api;
+pub mod capture;
+pub mod clock_sync;
+pub mod config;
+pub mod game;
+pub mod history;
+pub mod host_pin;
 pub mod net_sockets;
+pub mod players;
+pub mod sim;
+pub mod slots;
 pub mod websocket;
 
 pub type UiBackendRouter = Arc<UiBackendRouterInner>;
 
+/// Default capacity of the broadcast channels between the websocket clients and the board
+/// connections.
+///
+/// A receiver which falls behind by more messages loses the oldest ones, so the channels must
+/// hold a burst of presses from all boards.
+pub const CHANNEL_CAPACITY: usize = 1024;
+
 pub struct UiBackendRouterInner {
-    pub frontend_tx: broadcast::Sender<Message>,
-    pub frontend_rx: broadcast::Receiver<Message>,
-    pub board_tx: broadcast::Sender<Message>,
-    pub board_rx: broadcast::Receiver<Message>,
+    pub frontend_tx: broadcast::Sender<UiEvent>,
+    pub frontend_rx: broadcast::Receiver<UiEvent>,
+    /// Commands for the boards, each board connection picks the part for its slots.
+    pub board_tx: broadcast::Sender<UiCommand>,
+    pub board_rx: broadcast::Receiver<UiCommand>,
+    /// Boards which connected since startup, keyed by their board ID.
+    pub boards: Mutex<HashMap<u32, BoardInfo>>,
+    /// Last delivered button event per board ID, kept across reconnects.
+    pub delivered_presses: Mutex<HashMap<u32, PressCursor>>,
+    pub player_slots: Mutex<PlayerSlots>,
+    pub players: Mutex<PlayerRegistry>,
+    /// Latest snapshot of the game, sent to websocket clients when they connect.
+    pub game: Mutex<GameState>,
+    pub link: LinkConfig,
+    /// Records the board traffic if set.
+    pub capture: Option<Capture>,
+    /// Stores the played sessions if set.
+    pub history: Option<History>,
+    /// PIN of the websocket clients which control the game.
+    pub host_pin: HostPin,
+}
+
+impl UiBackendRouterInner {
+    /// Panics if a channel capacity is zero.
+    pub fn new(
+        link: LinkConfig,
+        channels: ChannelConfig,
+        capture: Option<Capture>,
+        history: Option<History>,
+        players: PlayerRegistry,
+        host_pin: String,
+    ) -> Self {
+        let (frontend_tx, frontend_rx) = broadcast::channel(channels.events);
+        let (board_tx, board_rx) = broadcast::channel(channels.commands);
+        Self {
+            frontend_tx,
+            frontend_rx,
+            board_tx,
+            board_rx,
+            boards: Mutex::new(HashMap::new()),
+            delivered_presses: Mutex::new(HashMap::new()),
+            player_slots: Mutex::new(PlayerSlots::default()),
+            players: Mutex::new(players),
+            game: Mutex::new(GameState::default()),
+            link,
+            capture,
+            history,
+            host_pin: HostPin::new(host_pin),
+        }
+    }
+}
+
+/// Liveness settings of the board connections.
+#[derive(Debug, Clone, Copy)]
+pub struct LinkConfig {
+    /// Interval of heartbeats sent to the boards.
+    pub heartbeat_interval: Duration,
+    /// Time without any message from a board after which it is considered disconnected.
+    pub heartbeat_timeout: Duration,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        LinkTiming::default().into()
+    }
+}
+
+impl From<LinkTiming> for LinkConfig {
+    fn from(timing: LinkTiming) -> Self {
+        Self {
+            heartbeat_interval: Duration::from_millis(timing.heartbeat_ms.into()),
+            heartbeat_timeout: Duration::from_millis(timing.timeout_ms.into()),
+        }
+    }
+}
+
+impl LinkConfig {
+    /// Settings for the board, which uses the same ones for its side of the link.
+    pub fn timing(&self) -> LinkTiming {
+        LinkTiming {
+            heartbeat_ms: self.heartbeat_interval.as_millis() as u32,
+            timeout_ms: self.heartbeat_timeout.as_millis() as u32,
+        }
+    }
+}
+
+/// What the server knows about a board.
+#[derive(Serialize, Debug, Clone)]
+pub struct BoardInfo {
+    /// Address of the latest connection.
+    pub addr: SocketAddr,
+    pub connected: bool,
+    /// Capabilities reported in the handshake.
+    pub hello: Hello,
+    /// Global player slots of the buttons.
+    pub slots: SlotRange,
+    /// Frames received from the board, including corrupt ones which were dropped.
+    pub frame_stats: FrameStats,
+    /// Estimate of the board clock, once the first ping was answered.
+    pub clock: Option<ClockEstimate>,
+    /// Latest status report of the board.
+    pub status: Option<BoardStatus>,
+    /// Configuration of the buttons by global slot, as read back from the board.
+    pub button_config: BTreeMap<u8, ButtonConfig>,
+}
+
+/// Position in the stream of button presses and releases of one board.
+#[derive(Debug, Clone, Copy)]
+pub struct PressCursor {
+    pub boot_id: u32,
+    pub last_seq: u32,
+}
+
+impl PressCursor {
+    pub fn new(boot_id: u32) -> Self {
+        Self {
+            boot_id,
+            last_seq: 0,
+        }
+    }
 }
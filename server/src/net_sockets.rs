@@ -1,59 +1,642 @@
-use common::{Message, MsgBuffer};
-use postcard::to_allocvec;
+use std::{
+    collections::BTreeMap,
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use common::{
+    decode_payload, encode_frame, features, hello_version, BoardToServer, EffectKind, Hello,
+    LedEffect, LedFrame, MsgBuffer, Ping, ServerToBoard, MAX_FRAME_SIZE, PROTOCOL_VERSION,
+    SERVER_ADDR,
+};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     net::TcpStream,
+    sync::broadcast::{self, error::RecvError},
+    time::{interval, sleep_until, timeout, timeout_at, Instant},
 };
 
-use crate::UiBackendRouter;
+use crate::{
+    capture::CapturedMessage,
+    clock_sync::{now_us, ClockSync, MappedTime},
+    players::players_event,
+    slots::SlotRange,
+    websocket::{DisconnectReason, UiButtonPress, UiButtonRelease, UiCommand, UiEvent},
+    BoardInfo, PressCursor, UiBackendRouter,
+};
+
+/// Time to wait for the board's `Hello` before rejecting it.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Interval of clock synchronization pings.
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time to wait for further LED updates which are merged into one frame.
+pub const LED_MERGE_WINDOW: Duration = Duration::from_millis(2);
 
 pub async fn board_connection(mut socket: TcpStream, uib_router: UiBackendRouter) {
-    let (mut reader, mut writer) = socket.split();
+    let addr = match socket.peer_addr() {
+        Ok(addr) => addr,
+        Err(e) => {
+            println!("Could not get board address: {e}");
+            return;
+        }
+    };
+    let (reader, writer) = socket.split();
+    serve_board(reader, writer, addr, uib_router).await;
+}
 
+/// Handle a board connection from the handshake until it ends.
+///
+/// Live connections come from [`board_connection`], replayed ones from [`crate::capture`].
+pub async fn serve_board<R, W>(
+    mut reader: R,
+    mut writer: W,
+    addr: SocketAddr,
+    uib_router: UiBackendRouter,
+) where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
     // Get server channels.
     let ui_tx = uib_router.frontend_tx.clone();
-    let mut board_rx = uib_router.board_rx.resubscribe();
 
     // Initialize buffer to read messages to.
     let mut read_buf = MsgBuffer::<2000>::default();
 
-    // Send init instruction.
-    let serialized = to_allocvec(&Message::InitBoard).unwrap();
-    if let Err(e) = writer.write_all(&serialized).await {
-        println!("Error in sending init data: {e}");
+    // Wait for the handshake. Messages which arrived together with it are forwarded afterwards.
+    let (hello, pending) =
+        match timeout(HELLO_TIMEOUT, read_handshake(&mut reader, &mut read_buf)).await {
+            Ok(Ok(Handshake::Hello(hello, pending))) => (hello, pending),
+            Ok(Ok(Handshake::Version(version))) => {
+                println!(
+                    "Rejecting board {addr}: protocol version {version} does not match server \
+                    version {PROTOCOL_VERSION}"
+                );
+                return;
+            }
+            Ok(Ok(Handshake::NotHello)) => {
+                println!("Rejecting board {addr}: first message was not Hello");
+                return;
+            }
+            Ok(Err(e)) => {
+                println!("Error in reading handshake from board {addr}: {e}");
+                return;
+            }
+            Err(_) => {
+                // Firmware before the framed protocol never sends a decodable Hello.
+                println!("Rejecting board {addr}: no Hello received, firmware may be outdated");
+                return;
+            }
+        };
+
+    if let Some(other) = address_conflict(&uib_router, addr, hello.board_id) {
+        println!(
+            "Rejecting board {addr}: board {other} is connected from the same address, flash one of \
+            them with another BOARD_IP_HOST"
+        );
+        return;
     }
+    let Some(slots) = uib_router
+        .player_slots
+        .lock()
+        .unwrap()
+        .assign(hello.board_id, hello.num_buttons)
+    else {
+        println!("Rejecting board {addr}: no player slots left");
+        return;
+    };
+    println!("Board {addr} connected with slots {slots:?}: {hello:?}");
+    if let Some(capture) = &uib_router.capture {
+        capture.record(
+            hello.board_id,
+            addr,
+            CapturedMessage::FromBoard(BoardToServer::Hello(hello.clone())),
+        );
+    }
+
+    // Sequence numbers restart when the board rebooted.
+    uib_router
+        .delivered_presses
+        .lock()
+        .unwrap()
+        .entry(hello.board_id)
+        .and_modify(|cursor| {
+            if cursor.boot_id != hello.boot_id {
+                *cursor = PressCursor::new(hello.boot_id);
+            }
+        })
+        .or_insert_with(|| PressCursor::new(hello.boot_id));
+
+    uib_router.boards.lock().unwrap().insert(
+        hello.board_id,
+        BoardInfo {
+            addr,
+            connected: true,
+            hello: hello.clone(),
+            slots,
+            frame_stats: read_buf.stats,
+            clock: None,
+            status: None,
+            button_config: BTreeMap::new(),
+        },
+    );
+    ui_tx
+        .send(UiEvent::BoardConnected {
+            board_id: hello.board_id,
+            slots,
+        })
+        .ok();
+    // The new slots may have gotten players.
+    ui_tx.send(players_event(&uib_router)).ok();
+
+    let mut session = BoardSession {
+        addr,
+        hello,
+        slots,
+        clock: ClockSync::default(),
+    };
+    let reason = session
+        .run(
+            &mut reader,
+            &mut writer,
+            &mut read_buf,
+            pending,
+            &uib_router,
+        )
+        .await;
+
+    // A newer connection of the same board may already have replaced this one.
+    session.update_info(&uib_router, |board| board.connected = false);
+    ui_tx
+        .send(UiEvent::BoardDisconnected {
+            board_id: session.hello.board_id,
+            reason,
+        })
+        .ok();
+}
 
+/// Other board connected from the IP address of a new board, which clash on the network.
+fn address_conflict(uib_router: &UiBackendRouter, addr: SocketAddr, board_id: u32) -> Option<u32> {
+    // Simulated boards run on the server host and share its addresses.
+    if addr.ip().is_loopback() || addr.ip() == Ipv4Addr::from(SERVER_ADDR) {
+        return None;
+    }
+    let boards = uib_router.boards.lock().unwrap();
+    boards
+        .values()
+        .find(|board| {
+            board.connected && board.addr.ip() == addr.ip() && board.hello.board_id != board_id
+        })
+        .map(|board| board.hello.board_id)
+}
+
+/// Read until at least one complete message was decoded.
+/// First message of a connection.
+enum Handshake {
+    /// With the messages which arrived together with it.
+    Hello(Hello, Vec<BoardToServer>),
+    /// `Hello` of another protocol version, whose other fields may not be decodable.
+    Version(u16),
+    NotHello,
+}
+
+async fn read_handshake<R, const N: usize>(
+    reader: &mut R,
+    read_buf: &mut MsgBuffer<N>,
+) -> io::Result<Handshake>
+where
+    R: AsyncRead + Unpin,
+{
     loop {
-        tokio::select! {
-            read = reader.read(read_buf.as_buf()) => {
-                match read {
-                    Ok(num_read) => {
-                        read_buf.cursor += num_read;
-                        read_buf.process_msgs_ok(|msg| {
-                            ui_tx.send(msg).ok();
-                        });
-                    }
-                    Err(e) => {
-                        println!("Error in reading data: {e}");
-                        return;
-                    }
+        let num_read = reader.read(read_buf.as_buf()).await?;
+        if num_read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        read_buf.cursor += num_read;
+
+        let mut version = None;
+        let mut msgs = Vec::new();
+        read_buf.process_payloads(|payload| {
+            // Check the version before decoding the whole Hello, which may have changed.
+            if version.is_none() && msgs.is_empty() {
+                version = hello_version(payload).filter(|&version| version != PROTOCOL_VERSION);
+                if version.is_some() {
+                    return true;
                 }
             }
-            recv = board_rx.recv() => {
-                match recv {
-                    Ok(msg) => match writer.write_all(&to_allocvec(&msg).unwrap()).await {
-                        Ok(()) => {}
+            decode_payload(payload).map(|msg| msgs.push(msg)).is_some()
+        });
+        if let Some(version) = version {
+            return Ok(Handshake::Version(version));
+        }
+        let mut msgs = msgs.into_iter();
+        match msgs.next() {
+            Some(BoardToServer::Hello(hello)) => {
+                return Ok(Handshake::Hello(hello, msgs.collect()))
+            }
+            Some(_) => return Ok(Handshake::NotHello),
+            None => {}
+        }
+    }
+}
+
+/// State of one board connection.
+struct BoardSession {
+    addr: SocketAddr,
+    hello: Hello,
+    slots: SlotRange,
+    clock: ClockSync,
+}
+
+impl BoardSession {
+    /// Exchange messages with the board until the connection ends.
+    async fn run<R, W, const N: usize>(
+        &mut self,
+        reader: &mut R,
+        writer: &mut W,
+        read_buf: &mut MsgBuffer<N>,
+        pending: Vec<BoardToServer>,
+        uib_router: &UiBackendRouter,
+    ) -> DisconnectReason
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let addr = self.addr;
+        let ui_tx = uib_router.frontend_tx.clone();
+        let mut board_rx = uib_router.board_rx.resubscribe();
+        let link = uib_router.link;
+
+        // Send init instruction.
+        let init = vec![ServerToBoard::InitBoard(link.timing())];
+        if let Err(e) = self.send(writer, uib_router, init).await {
+            println!("Error in sending init data: {e}");
+            return DisconnectReason::Error;
+        }
+        let get_config = vec![ServerToBoard::GetButtonConfig];
+        if let Err(e) = self.send(writer, uib_router, get_config).await {
+            println!("Error in requesting button config: {e}");
+            return DisconnectReason::Error;
+        }
+
+        let mut ping_interval = interval(PING_INTERVAL);
+        let mut next_ping_id: u32 = 0;
+        let mut heartbeat_interval = interval(link.heartbeat_interval);
+        // Any message from the board counts as a heartbeat.
+        let mut last_rx = Instant::now();
+
+        if let Some(seq) = self.handle_messages(uib_router, &ui_tx, pending, now_us()) {
+            let ack = vec![ServerToBoard::ButtonPressAck(seq)];
+            if let Err(e) = self.send(writer, uib_router, ack).await {
+                println!("Error in sending acknowledgement: {e}");
+                return DisconnectReason::Error;
+            }
+        }
+
+        loop {
+            tokio::select! {
+                read = reader.read(read_buf.as_buf()) => {
+                    match read {
+                        Ok(0) => {
+                            println!("Board {addr} closed the connection");
+                            return DisconnectReason::Closed;
+                        }
+                        Ok(num_read) => {
+                            last_rx = Instant::now();
+                            let rx_us = now_us();
+                            read_buf.cursor += num_read;
+                            let mut msgs = Vec::new();
+                            if !read_buf.process_msgs_ok(|msg| msgs.push(msg)) {
+                                println!("Dropped frames from board {addr}: {:?}", read_buf.stats);
+                            }
+                            let stats = read_buf.stats;
+                            self.update_info(uib_router, |board| board.frame_stats = stats);
+
+                            if let Some(seq) = self.handle_messages(uib_router, &ui_tx, msgs, rx_us) {
+                                let ack = vec![ServerToBoard::ButtonPressAck(seq)];
+                                if let Err(e) = self.send(writer, uib_router, ack).await {
+                                    println!("Error in sending acknowledgement: {e}");
+                                    return DisconnectReason::Error;
+                                }
+                            }
+                        }
                         Err(e) => {
-                            println!("Error in writing: {e}");
-                            return;
+                            println!("Error in reading data: {e}");
+                            return DisconnectReason::Error;
                         }
-                    },
-                    Err(e) => {
-                        println!("Error in receiving from channel: {e}");
-                        return;
                     }
                 }
-            },
+                recv = board_rx.recv() => {
+                    match recv {
+                        Ok(command) => {
+                            let Some(msg) = command.to_board(&self.slots) else {
+                                continue;
+                            };
+                            let msgs = merge_led_updates(msg, &mut board_rx, &self.slots).await;
+                            if let Err(e) = self.send(writer, uib_router, msgs).await {
+                                println!("Error in writing: {e}");
+                                return DisconnectReason::Error;
+                            }
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            println!("Board {addr} fell behind, skipped {skipped} commands");
+                        }
+                        Err(RecvError::Closed) => {
+                            println!("Command channel closed");
+                            return DisconnectReason::Error;
+                        }
+                    }
+                },
+                _ = ping_interval.tick() => {
+                    let ping = ServerToBoard::Ping(Ping {
+                        id: next_ping_id,
+                        server_tx_us: now_us(),
+                    });
+                    next_ping_id = next_ping_id.wrapping_add(1);
+                    if let Err(e) = self.send(writer, uib_router, vec![ping]).await {
+                        println!("Error in sending ping: {e}");
+                        return DisconnectReason::Error;
+                    }
+                },
+                _ = heartbeat_interval.tick() => {
+                    let heartbeat = vec![ServerToBoard::Heartbeat];
+                    if let Err(e) = self.send(writer, uib_router, heartbeat).await {
+                        println!("Error in sending heartbeat: {e}");
+                        return DisconnectReason::Error;
+                    }
+                },
+                _ = sleep_until(last_rx + link.heartbeat_timeout) => {
+                    println!(
+                        "No message from board {addr} for {:?}, disconnecting",
+                        link.heartbeat_timeout
+                    );
+                    return DisconnectReason::Timeout;
+                },
+            }
+        }
+    }
+
+    /// Send messages adapted to the capabilities of the board, recording them if capturing.
+    async fn send<W>(
+        &self,
+        writer: &mut W,
+        uib_router: &UiBackendRouter,
+        msgs: Vec<ServerToBoard>,
+    ) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut frame_buf = [0u8; MAX_FRAME_SIZE];
+        for msg in msgs
+            .into_iter()
+            .flat_map(|msg| adapt_to_board(&self.hello, msg))
+        {
+            let serialized = encode_frame(&msg, &mut frame_buf).unwrap();
+            writer.write_all(serialized).await?;
+            if let Some(capture) = &uib_router.capture {
+                capture.record(
+                    self.hello.board_id,
+                    self.addr,
+                    CapturedMessage::ToBoard(msg),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Update the stored info of the board, unless a newer connection replaced it.
+    fn update_info(&self, uib_router: &UiBackendRouter, update: impl FnOnce(&mut BoardInfo)) {
+        let mut boards = uib_router.boards.lock().unwrap();
+        if let Some(board) = boards.get_mut(&self.hello.board_id) {
+            if board.addr == self.addr {
+                update(board);
+            }
+        }
+    }
+
+    /// Forward messages from the board to the UI, dropping duplicated button events.
+    ///
+    /// `rx_us` is the server time at which the messages were read. Returns the sequence number
+    /// up to which button events should be acknowledged, if any.
+    fn handle_messages(
+        &mut self,
+        uib_router: &UiBackendRouter,
+        ui_tx: &broadcast::Sender<UiEvent>,
+        msgs: Vec<BoardToServer>,
+        rx_us: u64,
+    ) -> Option<u32> {
+        let mut ack = None;
+
+        for msg in msgs {
+            if let Some(capture) = &uib_router.capture {
+                capture.record(
+                    self.hello.board_id,
+                    self.addr,
+                    CapturedMessage::FromBoard(msg.clone()),
+                );
+            }
+            if let Some(seq) = msg.seq() {
+                // Duplicates are acknowledged again, the previous ack may have been lost.
+                ack = Some(seq);
+                if !self.is_new_event(uib_router, seq) {
+                    println!("Dropping duplicated button event: {msg:?}");
+                    continue;
+                }
+            }
+
+            match msg {
+                BoardToServer::ButtonPress(press) => {
+                    let Some(slot) = self.slot(press.button_id) else {
+                        continue;
+                    };
+                    let server_time = self.server_time(press.board_time_us);
+                    ui_tx
+                        .send(UiEvent::ButtonPress(UiButtonPress {
+                            press,
+                            board_id: self.hello.board_id,
+                            slot,
+                            server_time,
+                        }))
+                        .ok();
+                }
+                BoardToServer::ButtonRelease(release) => {
+                    let Some(slot) = self.slot(release.button_id) else {
+                        continue;
+                    };
+                    let server_time = self.server_time(release.board_time_us);
+                    ui_tx
+                        .send(UiEvent::ButtonRelease(UiButtonRelease {
+                            release,
+                            board_id: self.hello.board_id,
+                            slot,
+                            server_time,
+                        }))
+                        .ok();
+                }
+                BoardToServer::Pong(pong) => {
+                    self.clock.add(&pong, rx_us);
+                    let estimate = self.clock.estimate();
+                    self.update_info(uib_router, |board| board.clock = estimate);
+                }
+                BoardToServer::InitAck(board_us) => {
+                    println!("Board {} initialized the game at {board_us} us", self.addr);
+                }
+                BoardToServer::Heartbeat => {}
+                BoardToServer::ButtonConfig(report) => {
+                    let Some(slot) = self.slot(report.button_id) else {
+                        continue;
+                    };
+                    self.update_info(uib_router, |board| {
+                        board.button_config.insert(slot, report.config);
+                    });
+                }
+                BoardToServer::BoardStatus(status) => {
+                    self.update_info(uib_router, |board| board.status = Some(status.clone()));
+                    ui_tx
+                        .send(UiEvent::BoardStatus {
+                            board_id: self.hello.board_id,
+                            status,
+                        })
+                        .ok();
+                }
+                BoardToServer::Hello(hello) => println!("Ignoring repeated Hello: {hello:?}"),
+            }
+        }
+
+        ack
+    }
+
+    fn slot(&self, button_id: u8) -> Option<u8> {
+        let slot = self.slots.slot(button_id);
+        if slot.is_none() {
+            println!(
+                "Ignoring event of unknown button {button_id} from board {}",
+                self.addr
+            );
+        }
+        slot
+    }
+
+    /// Board time of an event mapped onto the server clock, once the board clock is known.
+    fn server_time(&self, board_time_us: u64) -> Option<MappedTime> {
+        self.clock.to_server_time(board_time_us)
+    }
+
+    fn is_new_event(&self, uib_router: &UiBackendRouter, seq: u32) -> bool {
+        let mut delivered = uib_router.delivered_presses.lock().unwrap();
+        let cursor = delivered
+            .entry(self.hello.board_id)
+            .or_insert_with(|| PressCursor::new(self.hello.boot_id));
+        if seq <= cursor.last_seq {
+            return false;
+        }
+        cursor.last_seq = seq;
+        true
+    }
+}
+
+/// Merge a burst of LED updates starting with `first` into a single frame.
+///
+/// Any other message ends the burst and is returned after the frame to keep the order. Commands
+/// which do not affect the board with `slots` are skipped.
+pub async fn merge_led_updates(
+    first: ServerToBoard,
+    board_rx: &mut broadcast::Receiver<UiCommand>,
+    slots: &SlotRange,
+) -> Vec<ServerToBoard> {
+    let mut frame = match first {
+        ServerToBoard::LedUpdate(update) => LedFrame::from(update),
+        ServerToBoard::LedFrame(frame) => frame,
+        msg => return vec![msg],
+    };
+
+    let deadline = Instant::now() + LED_MERGE_WINDOW;
+    loop {
+        let recv = timeout_at(deadline, board_rx.recv()).await;
+        let recv = recv.map(|command| command.map(|command| command.to_board(slots)));
+        match recv {
+            Ok(Ok(None)) => {}
+            Ok(Ok(Some(ServerToBoard::LedUpdate(update)))) => frame.set(&update),
+            Ok(Ok(Some(ServerToBoard::LedFrame(other)))) => frame.merge(&other),
+            Ok(Ok(Some(msg))) => return vec![ServerToBoard::LedFrame(frame), msg],
+            // A closed channel is noticed again by the next receive of the connection loop.
+            Ok(Err(_)) | Err(_) => return vec![ServerToBoard::LedFrame(frame)],
+        }
+    }
+}
+
+/// Adapt a message to the capabilities the board reported, dropping what it cannot handle.
+pub fn adapt_to_board(hello: &Hello, msg: ServerToBoard) -> Vec<ServerToBoard> {
+    match msg {
+        ServerToBoard::LedUpdate(update) => {
+            if hello.supports(features::LED_UPDATE) && update.button_id < hello.num_leds {
+                vec![ServerToBoard::LedUpdate(update)]
+            } else {
+                Vec::new()
+            }
+        }
+        ServerToBoard::LedFrame(frame) => {
+            let frame = LedFrame {
+                mask: frame.mask & LedFrame::all(hello.num_leds, true).mask,
+                on: frame.on,
+            };
+            if frame.mask == 0 {
+                Vec::new()
+            } else if hello.supports(features::LED_FRAME) {
+                vec![ServerToBoard::LedFrame(frame)]
+            } else if hello.supports(features::LED_UPDATE) {
+                frame.updates().map(ServerToBoard::LedUpdate).collect()
+            } else {
+                Vec::new()
+            }
+        }
+        ServerToBoard::LedEffect(effect) => {
+            let effect = LedEffect {
+                mask: effect.mask & LedFrame::all(hello.num_leds, true).mask,
+                ..effect
+            };
+            if effect.mask == 0 {
+                Vec::new()
+            } else if hello.supports(features::LED_EFFECTS) {
+                vec![ServerToBoard::LedEffect(effect)]
+            } else if let EffectKind::FlashThenHold { .. } = effect.kind {
+                // Without animation, at least show the final state.
+                adapt_to_board(
+                    hello,
+                    ServerToBoard::LedFrame(LedFrame {
+                        mask: effect.mask,
+                        on: effect.mask,
+                    }),
+                )
+            } else {
+                Vec::new()
+            }
+        }
+        // Without arming, the UI keeps filtering presses on its own.
+        msg @ (ServerToBoard::Arm(_) | ServerToBoard::Lock(_)) => {
+            if hello.supports(features::ARMING) {
+                vec![msg]
+            } else {
+                Vec::new()
+            }
+        }
+        // Without it, the UI still decides on the first press, just slower.
+        msg @ ServerToBoard::FirstPressWins(_) => {
+            if hello.supports(features::FIRST_PRESS_WINS) {
+                vec![msg]
+            } else {
+                Vec::new()
+            }
+        }
+        msg @ (ServerToBoard::ConfigureButtons(_) | ServerToBoard::GetButtonConfig) => {
+            if hello.supports(features::BUTTON_CONFIG) {
+                vec![msg]
+            } else {
+                Vec::new()
+            }
         }
+        msg => vec![msg],
     }
 }
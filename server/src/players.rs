@@ -0,0 +1,165 @@
+//! Display names, colors and sounds of the players, kept in a TOML file.
+//!
+//! ```toml
+//! [[player]]
+//! button_id = 0
+//! name = "Team Red"
+//! color = "#d62728"
+//! sound = "mario"
+//!
+//! # Only for the buttons of one board, when several boards are attached.
+//! [[player]]
+//! board_id = 1234
+//! button_id = 0
+//! name = "Team Blue"
+//! ```
+//!
+//! Sounds name a file in `assets/audio` without the `.mp3` extension.
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{slots::PlayerSlots, websocket::UiEvent, UiBackendRouter};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Player {
+    /// Board of the button, any board if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub board_id: Option<u32>,
+    pub button_id: u8,
+    pub name: String,
+    /// CSS color.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sound: Option<String>,
+}
+
+/// Player of a global slot, as sent to the websocket clients.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SlotPlayer {
+    pub slot: u8,
+    pub board_id: u32,
+    #[serde(flatten)]
+    pub player: Player,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct PlayersFile {
+    #[serde(default, rename = "player")]
+    players: Vec<Player>,
+}
+
+/// Players by button, saved to a file if loaded from one.
+#[derive(Debug)]
+pub struct PlayerRegistry {
+    players: Vec<Player>,
+    path: Option<PathBuf>,
+}
+
+impl Default for PlayerRegistry {
+    /// The buzzers of the original board.
+    fn default() -> Self {
+        let players = [
+            ("AoE2 Create", "create"),
+            ("Duck", "duck"),
+            ("ICQ", "icq"),
+            ("Mario", "mario"),
+            ("MGS", "mgs"),
+            ("Partyblower", "partyblower"),
+            ("Wololo", "wololo"),
+        ]
+        .into_iter()
+        .zip(0..)
+        .map(|((name, sound), button_id)| Player {
+            board_id: None,
+            button_id,
+            name: name.into(),
+            color: None,
+            sound: Some(sound.into()),
+        })
+        .collect();
+        Self {
+            players,
+            path: None,
+        }
+    }
+}
+
+impl PlayerRegistry {
+    /// Load the registry from `path`, starting with the defaults if it does not exist yet.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let players = match fs::read_to_string(path) {
+            Ok(content) => {
+                let file: PlayersFile = toml::from_str(&content)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                file.players
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Self::default().players,
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            players,
+            path: Some(path.to_owned()),
+        })
+    }
+
+    pub fn players(&self) -> &[Player] {
+        &self.players
+    }
+
+    /// Replace all players, saving them to the file of the registry.
+    pub fn set(&mut self, players: Vec<Player>) -> io::Result<()> {
+        if let Some(path) = &self.path {
+            let file = PlayersFile {
+                players: players.clone(),
+            };
+            let content = toml::to_string(&file)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            fs::write(path, content)?;
+        }
+        self.players = players;
+        Ok(())
+    }
+
+    /// Player of a button, preferring entries for the board over ones for any board.
+    pub fn lookup(&self, board_id: u32, button_id: u8) -> Option<&Player> {
+        let mut matching = self
+            .players
+            .iter()
+            .filter(|player| player.button_id == button_id);
+        let any_board = matching.clone().find(|player| player.board_id.is_none());
+        matching
+            .find(|player| player.board_id == Some(board_id))
+            .or(any_board)
+    }
+
+    /// Players of all assigned slots, ordered by slot.
+    pub fn by_slot(&self, slots: &PlayerSlots) -> Vec<SlotPlayer> {
+        let mut players: Vec<_> = slots
+            .ranges()
+            .flat_map(|(board_id, range)| {
+                (0..range.len).filter_map(move |button_id| {
+                    let player = self.lookup(board_id, button_id)?;
+                    Some(SlotPlayer {
+                        slot: range.slot(button_id)?,
+                        board_id,
+                        player: player.clone(),
+                    })
+                })
+            })
+            .collect();
+        players.sort_by_key(|player| player.slot);
+        players
+    }
+}
+
+/// Event with the players of all assigned slots.
+pub fn players_event(uib_router: &UiBackendRouter) -> UiEvent {
+    let slots = uib_router.player_slots.lock().unwrap();
+    UiEvent::Players(uib_router.players.lock().unwrap().by_slot(&slots))
+}
@@ -0,0 +1,398 @@
+//! Simulated boards speaking the board protocol, for development and load tests without hardware.
+use std::{
+    collections::VecDeque,
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use common::{
+    arming::{Arming, Verdict},
+    effects::{EffectKind, EffectLevel},
+    encode_frame, features, BoardToServer, ButtonPress, FirmwareVersion, Hello, LedEffect,
+    LedFrame, LinkTiming, MsgBuffer, Pong, ServerToBoard, MAX_FRAME_SIZE, PROTOCOL_VERSION,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc,
+    time::{interval, sleep, sleep_until},
+};
+
+pub const NUM_BUTTONS: usize = 7;
+pub const NUM_LEDS: usize = 6;
+
+/// Time to wait before connecting again.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Interval at which running LED effects are advanced.
+const LED_TICK: Duration = Duration::from_millis(20);
+
+/// Without real buttons, there are no releases or buttons to configure.
+const FEATURES: u32 = features::LED_UPDATE
+    | features::LED_FRAME
+    | features::LED_EFFECTS
+    | features::ARMING
+    | features::FIRST_PRESS_WINS;
+
+/// Input of a simulated board.
+#[derive(Debug, Clone, Copy)]
+pub enum SimInput {
+    Press(u8),
+    /// Drop the connection and stay offline for the given time.
+    Disconnect(Duration),
+}
+
+/// Notified about what a simulated board does.
+pub trait SimObserver {
+    fn log(&mut self, _msg: &str) {}
+    fn leds_changed(&mut self, _leds: &Leds) {}
+}
+
+/// Protocol state of a simulated board, kept across reconnects like on the firmware.
+pub struct SimBoard {
+    hello: Hello,
+    boot: Instant,
+    /// Board clock in microseconds when the current game was initialized.
+    game_start_us: Option<u64>,
+    next_seq: u32,
+    /// Button events which were not acknowledged yet, retransmitted after reconnecting.
+    unacked: VecDeque<BoardToServer>,
+    arming: Arming,
+    leds: Leds,
+    /// Liveness settings of the current connection, like on the firmware.
+    link: LinkTiming,
+}
+
+impl SimBoard {
+    pub fn new(board_id: u32) -> Self {
+        Self {
+            hello: Hello {
+                protocol_version: PROTOCOL_VERSION,
+                firmware_version: FirmwareVersion {
+                    major: 0,
+                    minor: 0,
+                    patch: 0,
+                },
+                board_id,
+                num_buttons: NUM_BUTTONS as u8,
+                num_leds: NUM_LEDS as u8,
+                features: FEATURES,
+                boot_id: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .subsec_nanos(),
+            },
+            boot: Instant::now(),
+            game_start_us: None,
+            next_seq: 1,
+            unacked: VecDeque::new(),
+            arming: Arming::new(),
+            leds: Leds::default(),
+            link: LinkTiming::default(),
+        }
+    }
+
+    pub fn board_id(&self) -> u32 {
+        self.hello.board_id
+    }
+
+    /// Button presses forwarded to the server so far.
+    pub fn presses_sent(&self) -> u32 {
+        self.next_seq - 1
+    }
+
+    /// Board clock in microseconds since start.
+    pub fn now_us(&self) -> u64 {
+        self.boot.elapsed().as_micros() as u64
+    }
+
+    /// Connect to the server and handle inputs until the input channel is closed.
+    pub async fn run(
+        &mut self,
+        server_addr: SocketAddr,
+        inputs: &mut mpsc::UnboundedReceiver<SimInput>,
+        observer: &mut impl SimObserver,
+    ) {
+        loop {
+            observer.log(&format!("Connecting to {server_addr}"));
+            let result = match TcpStream::connect(server_addr).await {
+                Ok(stream) => self.connection(stream, inputs, observer).await,
+                Err(e) => Err(e),
+            };
+            let offline = match result {
+                Ok(None) => return,
+                Ok(Some(offline)) => {
+                    observer.log(&format!("Disconnecting for {offline:?}"));
+                    offline
+                }
+                Err(e) => {
+                    observer.log(&format!("Connection lost: {e}, reconnecting"));
+                    RECONNECT_DELAY
+                }
+            };
+
+            // Presses while offline are sent after reconnecting, like on the board.
+            let reconnect = sleep(offline);
+            tokio::pin!(reconnect);
+            loop {
+                tokio::select! {
+                    _ = &mut reconnect => break,
+                    input = inputs.recv() => match input {
+                        Some(SimInput::Press(button_id)) => {
+                            self.press(button_id, observer);
+                        }
+                        Some(SimInput::Disconnect(_)) => {}
+                        None => return,
+                    },
+                }
+            }
+        }
+    }
+
+    /// Exchange messages with the server until the connection is lost.
+    ///
+    /// Returns the requested offline time, or `None` once the input channel is closed.
+    async fn connection(
+        &mut self,
+        mut stream: TcpStream,
+        inputs: &mut mpsc::UnboundedReceiver<SimInput>,
+        observer: &mut impl SimObserver,
+    ) -> io::Result<Option<Duration>> {
+        let (mut reader, mut writer) = stream.split();
+        let mut read_buf = MsgBuffer::<1024>::default();
+        let mut frame_buf = [0u8; MAX_FRAME_SIZE];
+
+        let hello = BoardToServer::Hello(self.hello.clone());
+        writer
+            .write_all(encode_frame(&hello, &mut frame_buf).unwrap())
+            .await?;
+        for event in &self.unacked {
+            writer
+                .write_all(encode_frame(event, &mut frame_buf).unwrap())
+                .await?;
+        }
+        observer.log("Connected");
+
+        self.link = LinkTiming::default();
+        let mut next_heartbeat = tokio::time::Instant::now();
+        let mut led_interval = interval(LED_TICK);
+        let mut last_rx = tokio::time::Instant::now();
+
+        loop {
+            let heartbeat_interval = Duration::from_millis(self.link.heartbeat_ms.into());
+            let heartbeat_timeout = Duration::from_millis(self.link.timeout_ms.into());
+            let mut replies = Vec::new();
+            tokio::select! {
+                read = reader.read(read_buf.as_buf()) => {
+                    let num_read = read?;
+                    if num_read == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    last_rx = tokio::time::Instant::now();
+                    let rx_us = self.now_us();
+                    read_buf.cursor += num_read;
+                    let mut msgs = Vec::new();
+                    if !read_buf.process_msgs_ok(|msg| msgs.push(msg)) {
+                        observer.log(&format!("Dropped frames from server: {:?}", read_buf.stats));
+                    }
+                    for msg in msgs {
+                        self.handle_message(msg, rx_us, &mut replies, observer);
+                    }
+                }
+                input = inputs.recv() => match input {
+                    Some(SimInput::Press(button_id)) => {
+                        replies.extend(self.press(button_id, observer));
+                    }
+                    Some(SimInput::Disconnect(offline)) => return Ok(Some(offline)),
+                    None => return Ok(None),
+                },
+                _ = sleep_until(next_heartbeat) => {
+                    next_heartbeat += heartbeat_interval;
+                    replies.push(BoardToServer::Heartbeat);
+                }
+                _ = led_interval.tick() => {}
+                _ = sleep_until(last_rx + heartbeat_timeout) => {
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+            }
+
+            for mut reply in replies {
+                if let BoardToServer::Pong(pong) = &mut reply {
+                    pong.board_tx_us = self.now_us();
+                }
+                writer
+                    .write_all(encode_frame(&reply, &mut frame_buf).unwrap())
+                    .await?;
+            }
+
+            if self.leds.update(Instant::now()) {
+                observer.leds_changed(&self.leds);
+            }
+        }
+    }
+
+    fn handle_message(
+        &mut self,
+        msg: ServerToBoard,
+        rx_us: u64,
+        replies: &mut Vec<BoardToServer>,
+        observer: &mut impl SimObserver,
+    ) {
+        match msg {
+            ServerToBoard::InitBoard(_) | ServerToBoard::InitReactionGame(_) => {
+                if let ServerToBoard::InitBoard(link) = msg {
+                    self.link = link;
+                }
+                let now_us = self.now_us();
+                self.game_start_us = Some(now_us);
+                observer.log("Game initialized");
+                replies.push(BoardToServer::InitAck(now_us));
+            }
+            ServerToBoard::Ping(ping) => replies.push(BoardToServer::Pong(Pong {
+                id: ping.id,
+                server_tx_us: ping.server_tx_us,
+                board_rx_us: rx_us,
+                board_tx_us: rx_us,
+            })),
+            ServerToBoard::Heartbeat => {}
+            ServerToBoard::ButtonPressAck(seq) => {
+                // Acknowledgements are cumulative.
+                while self
+                    .unacked
+                    .front()
+                    .and_then(BoardToServer::seq)
+                    .is_some_and(|event_seq| event_seq <= seq)
+                {
+                    self.unacked.pop_front();
+                }
+            }
+            ServerToBoard::Arm(mask) => self.arming.arm(mask),
+            ServerToBoard::Lock(lock) => self.arming.lock(lock),
+            ServerToBoard::FirstPressWins(mode) => self.arming.set_first_press_wins(mode),
+            ServerToBoard::LedUpdate(update) => self.leds.frame(&update.into()),
+            ServerToBoard::LedFrame(frame) => self.leds.frame(&frame),
+            ServerToBoard::LedEffect(effect) => self.leds.effect(&effect, Instant::now()),
+            msg => observer.log(&format!("Ignoring unsupported message: {msg:?}")),
+        }
+    }
+
+    /// Press of a button, returned as the message to send unless the button is locked.
+    fn press(&mut self, button_id: u8, observer: &mut impl SimObserver) -> Option<BoardToServer> {
+        if button_id as usize >= NUM_BUTTONS {
+            observer.log(&format!("Ignoring press of unknown button {button_id}"));
+            return None;
+        }
+        let time_us = self.now_us();
+        let Verdict::Forward {
+            locked,
+            first_after_arm,
+        } = self.arming.on_press(button_id)
+        else {
+            observer.log(&format!("Discarding press of locked button {button_id}"));
+            return None;
+        };
+        if first_after_arm && self.arming.first_press_wins() {
+            self.leds.frame(&LedFrame {
+                mask: LedFrame::all(NUM_LEDS as u8, false).mask,
+                on: 1 << button_id,
+            });
+        }
+
+        let (micros_since_init, before_game) = match self.game_start_us {
+            Some(start_us) => (time_us - start_us, false),
+            None => (0, true),
+        };
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        observer.log(&format!(
+            "Button {button_id} pressed {micros_since_init} us after init"
+        ));
+
+        let press = BoardToServer::ButtonPress(ButtonPress {
+            button_id,
+            board_time_us: time_us,
+            micros_since_init,
+            before_game,
+            locked,
+            first_after_arm,
+            seq,
+        });
+        self.unacked.push_back(press.clone());
+        Some(press)
+    }
+}
+
+/// Effect running on a single LED.
+#[derive(Clone, Copy)]
+struct RunningEffect {
+    kind: EffectKind,
+    started: Instant,
+    /// Position of the LED among all LEDs of the effect.
+    rank: u8,
+    /// Number of LEDs running the effect.
+    num: u8,
+}
+
+/// LEDs of a simulated board.
+#[derive(Default)]
+pub struct Leds {
+    on: [bool; NUM_LEDS],
+    effects: [Option<RunningEffect>; NUM_LEDS],
+    /// Set when a change was not reported yet.
+    dirty: bool,
+}
+
+impl Leds {
+    pub fn is_on(&self, idx: usize) -> bool {
+        self.on[idx]
+    }
+
+    fn frame(&mut self, frame: &LedFrame) {
+        for idx in 0..NUM_LEDS {
+            if let Some(on) = frame.get(idx) {
+                // Static levels replace running effects.
+                self.effects[idx] = None;
+                self.on[idx] = on;
+            }
+        }
+        self.dirty = true;
+    }
+
+    fn effect(&mut self, effect: &LedEffect, started: Instant) {
+        let leds = (0..NUM_LEDS).filter(|idx| effect.mask & (1 << idx) != 0);
+        let num = leds.clone().count() as u8;
+        for (rank, idx) in leds.enumerate() {
+            self.effects[idx] = Some(RunningEffect {
+                kind: effect.kind,
+                started,
+                rank: rank as u8,
+                num,
+            });
+        }
+    }
+
+    /// Advance the running effects, returns whether anything changed.
+    fn update(&mut self, now: Instant) -> bool {
+        for (on, slot) in self.on.iter_mut().zip(self.effects.iter_mut()) {
+            let Some(effect) = *slot else {
+                continue;
+            };
+            let elapsed_ms = (now - effect.started).as_millis() as u32;
+            let level = match effect.kind.level(elapsed_ms, effect.rank, effect.num) {
+                // Software PWM is not worth simulating.
+                EffectLevel::Running(_) if matches!(effect.kind, EffectKind::Pulse { .. }) => true,
+                EffectLevel::Running(level) => level,
+                EffectLevel::Done(level) => {
+                    *slot = None;
+                    level
+                }
+            };
+            if *on != level {
+                *on = level;
+                self.dirty = true;
+            }
+        }
+        std::mem::take(&mut self.dirty)
+    }
+}
@@ -0,0 +1,83 @@
+//! Global player slots spanning the buttons of all boards.
+//!
+//! Every board gets a contiguous range of slots when it first connects, so that several boards
+//! can run one game. The UI only sees slots, and masks over slots are translated into the
+//! local button masks of each board.
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+/// Number of slots which fit into a slot mask.
+pub const MAX_SLOTS: u8 = u64::BITS as u8;
+
+/// Buttons of one board as a range of global player slots.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotRange {
+    pub first: u8,
+    pub len: u8,
+}
+
+impl SlotRange {
+    /// Global slot of a button of this board.
+    pub fn slot(&self, button_id: u8) -> Option<u8> {
+        (button_id < self.len).then_some(self.first + button_id)
+    }
+
+    /// Button of this board assigned to a global slot.
+    pub fn button(&self, slot: u8) -> Option<u8> {
+        slot.checked_sub(self.first)
+            .filter(|button_id| *button_id < self.len)
+    }
+
+    /// Part of a mask over global slots which belongs to this board, as a mask over its buttons.
+    pub fn local_mask(&self, mask: u64) -> u16 {
+        let own = 1u64
+            .checked_shl(self.len as u32)
+            .map_or(u64::MAX, |bit| bit - 1);
+        (mask.checked_shr(self.first as u32).unwrap_or(0) & own) as u16
+    }
+}
+
+/// Slot ranges by board ID, kept across reconnects.
+///
+/// Slots are handed out in the order in which boards first connect.
+#[derive(Debug, Default)]
+pub struct PlayerSlots {
+    ranges: HashMap<u32, SlotRange>,
+    next: u8,
+}
+
+impl PlayerSlots {
+    /// Slot ranges by board ID.
+    pub fn ranges(&self) -> impl Iterator<Item = (u32, SlotRange)> + '_ {
+        self.ranges
+            .iter()
+            .map(|(board_id, range)| (*board_id, *range))
+    }
+
+    /// Slots of a board, assigning new ones if it is unknown or has more buttons than before.
+    ///
+    /// Returns `None` if all slots are taken.
+    pub fn assign(&mut self, board_id: u32, num_buttons: u8) -> Option<SlotRange> {
+        if let Some(range) = self.ranges.get(&board_id) {
+            if range.len >= num_buttons {
+                return Some(SlotRange {
+                    len: num_buttons,
+                    ..*range
+                });
+            }
+        }
+
+        let len = num_buttons.min(u16::BITS as u8);
+        if MAX_SLOTS - self.next < len {
+            return None;
+        }
+        let range = SlotRange {
+            first: self.next,
+            len,
+        };
+        self.next += len;
+        self.ranges.insert(board_id, range);
+        Some(range)
+    }
+}
@@ -1,8 +1,11 @@
 //! Websocket connection module.
 //!
+//! Clients pick their [`Role`] with the query, e.g. `/ws?role=host&pin=1234`. Only the host may
+//! send commands.
+//!
 //! Test in the browser with:
-//! ```no_run
-//! var conn = new WebSocket('ws://127.0.0.1:3001/ws');
+//! ```js
+//! var conn = new WebSocket('ws://127.0.0.1:3000/ws?role=display');
 //! conn.addEventListener("message", (event) => console.log(event));
 //! conn.send("Hello from frontend");
 //! ```
@@ -11,35 +14,254 @@ use std::net::SocketAddr;
 use axum::{
     extract::{
         ws::{self, WebSocket},
-        ConnectInfo, WebSocketUpgrade,
+        ConnectInfo, Query, WebSocketUpgrade,
     },
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     Extension,
 };
+use common::{
+    BoardStatus, ButtonConfig, ButtonPress, ButtonRelease, ConfigureButtons, EffectKind, LedEffect,
+    LedFrame, LedUpdate, Lock, LockMode, ServerToBoard,
+};
 use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    clock_sync::MappedTime,
+    game::{GameCommand, GameState},
+    players::{players_event, SlotPlayer},
+    slots::SlotRange,
+    UiBackendRouter,
+};
+
+/// Events pushed to the websocket clients.
+#[derive(Serialize, Debug, Clone)]
+pub enum UiEvent {
+    ButtonPress(UiButtonPress),
+    ButtonRelease(UiButtonRelease),
+    BoardConnected {
+        board_id: u32,
+        slots: SlotRange,
+    },
+    BoardDisconnected {
+        board_id: u32,
+        reason: DisconnectReason,
+    },
+    BoardStatus {
+        board_id: u32,
+        status: BoardStatus,
+    },
+    GameState(GameState),
+    /// Secret countdown of a new reaction round, only sent to the host.
+    Countdown {
+        round: u32,
+        countdown_ms: u32,
+    },
+    /// Players of all assigned slots, sent when they change.
+    Players(Vec<SlotPlayer>),
+    /// The reaction countdown of the round ended, sent to all clients at once.
+    Go {
+        round: u32,
+    },
+}
+
+/// Why the connection to a board ended.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The board closed the connection.
+    Closed,
+    /// No message was received within the heartbeat timeout.
+    Timeout,
+    /// Reading from or writing to the connection failed.
+    Error,
+}
+
+/// Button press with its time mapped onto the server clock, once the board clock is known.
+#[derive(Serialize, Debug, Clone)]
+pub struct UiButtonPress {
+    #[serde(flatten)]
+    pub press: ButtonPress,
+    pub board_id: u32,
+    /// Global player slot of the button.
+    pub slot: u8,
+    pub server_time: Option<MappedTime>,
+}
+
+/// Button release with its time mapped onto the server clock, once the board clock is known.
+#[derive(Serialize, Debug, Clone)]
+pub struct UiButtonRelease {
+    #[serde(flatten)]
+    pub release: ButtonRelease,
+    pub board_id: u32,
+    /// Global player slot of the button.
+    pub slot: u8,
+    pub server_time: Option<MappedTime>,
+}
+
+/// Commands accepted from websocket clients.
+///
+/// Buttons are addressed by their global player slot, masks have bit `i` set for slot `i`.
+#[derive(Deserialize, Debug, Clone)]
+pub enum UiCommand {
+    InitReactionGame(u32),
+    LedUpdate(UiLedUpdate),
+    LedFrame(UiLedFrame),
+    LedEffect(UiLedEffect),
+    Arm(u64),
+    Lock(UiLock),
+    /// Decided by each board on its own, so there may be one winner per board.
+    FirstPressWins(Option<LockMode>),
+    ConfigureButtons(UiConfigureButtons),
+    /// Handled by the game, which sends the resulting commands to the boards.
+    Game(GameCommand),
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct UiLedUpdate {
+    pub slot: u8,
+    pub on: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct UiLedFrame {
+    pub mask: u64,
+    pub on: u64,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct UiLedEffect {
+    pub mask: u64,
+    pub kind: EffectKind,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct UiLock {
+    pub mask: u64,
+    pub mode: LockMode,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct UiConfigureButtons {
+    pub mask: u64,
+    pub config: ButtonConfig,
+}
+
+impl UiCommand {
+    /// The part of the command for the board owning `slots`, if it is affected.
+    pub fn to_board(&self, slots: &SlotRange) -> Option<ServerToBoard> {
+        let msg = match *self {
+            UiCommand::InitReactionGame(countdown) => ServerToBoard::InitReactionGame(countdown),
+            UiCommand::LedUpdate(update) => ServerToBoard::LedUpdate(LedUpdate {
+                button_id: slots.button(update.slot)?,
+                on: update.on,
+            }),
+            UiCommand::LedFrame(frame) => ServerToBoard::LedFrame(LedFrame {
+                mask: nonzero(slots.local_mask(frame.mask))?,
+                on: slots.local_mask(frame.on),
+            }),
+            UiCommand::LedEffect(effect) => ServerToBoard::LedEffect(LedEffect {
+                mask: nonzero(slots.local_mask(effect.mask))?,
+                kind: effect.kind,
+            }),
+            // Every board starts a new round, even if none of its buttons are unlocked.
+            UiCommand::Arm(mask) => ServerToBoard::Arm(slots.local_mask(mask)),
+            UiCommand::Lock(lock) => ServerToBoard::Lock(Lock {
+                mask: nonzero(slots.local_mask(lock.mask))?,
+                mode: lock.mode,
+            }),
+            UiCommand::FirstPressWins(mode) => ServerToBoard::FirstPressWins(mode),
+            UiCommand::ConfigureButtons(configure) => {
+                ServerToBoard::ConfigureButtons(ConfigureButtons {
+                    mask: nonzero(slots.local_mask(configure.mask))?,
+                    config: configure.config,
+                })
+            }
+            UiCommand::Game(_) => return None,
+        };
+        Some(msg)
+    }
+}
+
+fn nonzero(mask: u16) -> Option<u16> {
+    (mask != 0).then_some(mask)
+}
+
+/// What a websocket client may do and see.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Controls the game and sees everything, authenticated with the host PIN.
+    Host,
+    /// Screen showing the game, with the events of boards and buttons.
+    Display,
+    /// Only sees the game.
+    #[default]
+    Spectator,
+}
+
+impl Role {
+    /// Whether the event is part of the view of this role.
+    pub fn sees(self, event: &UiEvent) -> bool {
+        match self {
+            Role::Host => true,
+            Role::Display => !matches!(event, UiEvent::Countdown { .. }),
+            Role::Spectator => matches!(
+                event,
+                UiEvent::GameState(_) | UiEvent::Go { .. } | UiEvent::Players(_)
+            ),
+        }
+    }
+}
 
-use crate::UiBackendRouter;
+#[derive(Deserialize, Debug)]
+pub struct WsParams {
+    #[serde(default)]
+    role: Role,
+    pin: Option<String>,
+}
 
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(params): Query<WsParams>,
     Extension(uib_router): Extension<UiBackendRouter>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| process_websocket(socket, addr, uib_router))
+) -> Response {
+    if params.role == Role::Host {
+        if let Err(e) = uib_router.host_pin.check(params.pin.as_deref()) {
+            println!("Rejecting websocket client {addr} as host: {e:?}");
+            return e.status().into_response();
+        }
+    }
+    ws.on_upgrade(move |socket| process_websocket(socket, addr, params.role, uib_router))
+        .into_response()
 }
 
-async fn process_websocket(stream: WebSocket, addr: SocketAddr, uib_router: UiBackendRouter) {
-    println!("New websocket client: {}", addr);
+async fn process_websocket(
+    stream: WebSocket,
+    addr: SocketAddr,
+    role: Role,
+    uib_router: UiBackendRouter,
+) {
+    println!("New websocket client: {} ({:?})", addr, role);
 
     // By splitting, we can send and receive at the same time.
     let (mut sender, mut receiver) = stream.split();
 
     let mut ui_rx = uib_router.frontend_rx.resubscribe();
+    let game = uib_router.game.lock().unwrap().clone();
+    let mut snapshot = [players_event(&uib_router), UiEvent::GameState(game)].into_iter();
 
     // Receive updates from board.
     tokio::spawn(async move {
         loop {
-            match ui_rx.recv().await {
+            // New clients start with the players and the current game, before any update.
+            let recv = match snapshot.next() {
+                Some(event) => Ok(event),
+                None => ui_rx.recv().await,
+            };
+            match recv {
+                Ok(msg) if !role.sees(&msg) => {}
                 Ok(msg) => {
                     let msg = serde_json::to_string(&msg).unwrap();
                     println!("  To frontend (via {}): {}", addr, msg);
@@ -51,7 +273,10 @@ async fn process_websocket(stream: WebSocket, addr: SocketAddr, uib_router: UiBa
                         return;
                     }
                 }
-                Err(e) => println!("Error receiving: {e}"),
+                Err(RecvError::Lagged(skipped)) => {
+                    println!("Websocket client {addr} fell behind, skipped {skipped} events");
+                }
+                Err(RecvError::Closed) => return,
             }
         }
     });
@@ -60,15 +285,17 @@ async fn process_websocket(stream: WebSocket, addr: SocketAddr, uib_router: UiBa
     while let Some(Ok(message)) = receiver.next().await {
         if let ws::Message::Text(msg) = message {
             println!("From frontend (via {}): {}", addr, msg);
+            if role != Role::Host {
+                println!("Ignoring command from {addr}, only the host may send commands");
+                continue;
+            }
 
-            match serde_json::from_str(&msg) {
-                Ok(msg) => {
-                    uib_router.board_tx.send(msg).ok();
+            match serde_json::from_str::<UiCommand>(&msg) {
+                Ok(command) => {
+                    uib_router.board_tx.send(command).ok();
                 }
-                Err(_) => {}
+                Err(e) => println!("Ignoring invalid command from {addr}: {e}"),
             }
         }
     }
-
-    return;
 }
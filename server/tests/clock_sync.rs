@@ -0,0 +1,63 @@
+use common::Pong;
+use server::clock_sync::ClockSync;
+
+const START_US: u64 = 1_700_000_000_000_000;
+const OFFSET_US: f64 = 5_000_000.0;
+const DRIFT_PPM: f64 = 50.0;
+
+/// Board clock at server time `server_us`.
+fn board_us(server_us: u64) -> u64 {
+    (server_us as f64 + OFFSET_US + (server_us - START_US) as f64 * DRIFT_PPM * 1e-6) as u64
+}
+
+/// Exchange started at `server_tx_us` with the given delays on the way to the board and back.
+fn exchange(clock: &mut ClockSync, server_tx_us: u64, to_board_us: u64, to_server_us: u64) {
+    let board_rx_us = board_us(server_tx_us + to_board_us);
+    let pong = Pong {
+        id: 0,
+        server_tx_us,
+        board_rx_us,
+        board_tx_us: board_rx_us + 10,
+    };
+    clock.add(&pong, server_tx_us + to_board_us + 10 + to_server_us);
+}
+
+#[test]
+fn estimate_recovers_offset_and_drift() {
+    let mut clock = ClockSync::default();
+    assert!(clock.estimate().is_none());
+    // The way to the board is slower, which the error bound has to cover.
+    for i in 0..20 {
+        exchange(&mut clock, START_US + i * 1_000_000, 300, 100);
+    }
+
+    let estimate = clock.estimate().unwrap();
+    let reference_us = estimate.reference_us as u64;
+    let true_offset = board_us(reference_us) as f64 - reference_us as f64;
+    assert!((estimate.offset_us - true_offset).abs() <= estimate.error_us);
+    assert!((estimate.offset_us - true_offset - 100.0).abs() < 2.0);
+    assert!((estimate.drift_us_per_s - DRIFT_PPM).abs() < 0.1);
+    assert!(estimate.error_us >= 100.0 && estimate.error_us < 210.0);
+    assert_eq!(estimate.samples, 20);
+
+    let press_us = START_US + 25_000_000;
+    let mapped = clock.to_server_time(board_us(press_us)).unwrap();
+    assert!(mapped.server_time_us.abs_diff(press_us) <= mapped.error_us);
+}
+
+#[test]
+fn slow_exchanges_are_left_out() {
+    let mut clock = ClockSync::default();
+    for i in 0..10 {
+        exchange(&mut clock, START_US + i * 1_000_000, 200, 200);
+    }
+    let before = clock.estimate().unwrap();
+    // Queued on the way back, the offset of this exchange is off by 25 ms.
+    exchange(&mut clock, START_US + 10_000_000, 200, 50_000);
+
+    let after = clock.estimate().unwrap();
+    assert_eq!(after.samples, 10);
+    assert_eq!(after.reference_us, before.reference_us);
+    assert!((after.offset_us - before.offset_us).abs() < 1.0);
+    assert!(after.error_us < 210.0);
+}
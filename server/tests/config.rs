@@ -0,0 +1,42 @@
+use std::{fs, net::SocketAddr};
+
+use server::config::{ChannelConfig, Config, LinkSettings};
+
+#[test]
+fn missing_keys_keep_their_defaults() {
+    let path = std::env::temp_dir().join(format!("server-config-{}.toml", std::process::id()));
+    fs::write(
+        &path,
+        "http_listen = \"0.0.0.0:8080\"\n[channels]\nevents = 64\n[link]\ntimeout_ms = 5000\n",
+    )
+    .unwrap();
+    let config = Config::load(&path);
+    fs::remove_file(&path).ok();
+
+    let defaults = Config::default();
+    assert_eq!(
+        config.unwrap(),
+        Config {
+            http_listen: SocketAddr::from(([0, 0, 0, 0], 8080)),
+            channels: ChannelConfig {
+                events: 64,
+                ..defaults.channels
+            },
+            link: LinkSettings {
+                timeout_ms: 5000,
+                ..defaults.link
+            },
+            ..defaults
+        }
+    );
+}
+
+#[test]
+fn link_timeout_must_exceed_the_heartbeat_interval() {
+    let mut config = Config::default();
+    config.link.timeout_ms = config.link.heartbeat_ms;
+    assert!(config
+        .validate()
+        .unwrap_err()
+        .contains("heartbeat interval"));
+}
@@ -0,0 +1,187 @@
+use common::{ButtonPress, LedFrame, LockMode, ServerToBoard};
+use server::{
+    clock_sync::MappedTime,
+    game::{
+        Game, GameCommand, GameState, Reaction, Round, RoundPhase, COUNTDOWN_MS, SETTLE_WINDOW,
+    },
+    slots::SlotRange,
+    websocket::{UiButtonPress, UiCommand},
+};
+
+const START_US: u64 = 1_700_000_000_000_000;
+
+fn press(slot: u8, micros_since_init: u64) -> UiButtonPress {
+    UiButtonPress {
+        press: ButtonPress {
+            button_id: slot,
+            board_time_us: 0,
+            micros_since_init,
+            before_game: false,
+            locked: false,
+            first_after_arm: false,
+            seq: 1,
+        },
+        board_id: 1,
+        slot,
+        server_time: None,
+    }
+}
+
+/// Press at `reaction_us` after the go, mapped onto the server clock.
+fn timed_press(slot: u8, round: &Round, reaction_us: i64) -> UiButtonPress {
+    UiButtonPress {
+        server_time: Some(MappedTime {
+            server_time_us: (round.go_us as i64 + reaction_us) as u64,
+            error_us: 100,
+        }),
+        ..press(slot, 0)
+    }
+}
+
+fn reaction_round(game: &Game) -> Round {
+    let GameState::Reaction(round) = game.state() else {
+        panic!("no reaction round: {:?}", game.state());
+    };
+    round.clone()
+}
+
+#[test]
+fn reaction_counts_the_first_press_per_slot_after_the_countdown() {
+    let mut game = Game::default();
+    assert!(!game.press(&press(0, 5_000_000), START_US));
+    game.command(GameCommand::StartReaction, START_US);
+    let round = reaction_round(&game);
+    assert_eq!(round.phase, RoundPhase::Countdown);
+    assert!(COUNTDOWN_MS.contains(&round.countdown_ms));
+    assert_eq!(
+        game.go_us(),
+        Some(START_US + round.countdown_ms as u64 * 1000)
+    );
+
+    assert!(game.press(&timed_press(1, &round, -500_000), START_US));
+    assert_eq!(game.go(), Some(round.number));
+    assert_eq!(game.go_us(), None);
+    let arrival_us = round.go_us + 301_000;
+    assert!(game.press(&timed_press(2, &round, 300_000), arrival_us));
+    assert_eq!(
+        game.decide_us(),
+        Some(arrival_us + SETTLE_WINDOW.as_micros() as u64)
+    );
+    // Without a clock estimate, the board time since the game init is used.
+    let untimed = press(3, round.countdown_ms as u64 * 1000 + 400_000);
+    assert!(game.press(&untimed, arrival_us));
+    // Only the first press of a slot counts.
+    assert!(!game.press(&timed_press(1, &round, 500_000), arrival_us));
+    assert!(!game.press(&timed_press(2, &round, 600_000), arrival_us));
+
+    let flash = game.decide().unwrap();
+    assert!(matches!(flash[..], [_, UiCommand::LedEffect(effect)] if effect.mask == 1 << 2));
+    assert_eq!(game.decide_us(), None);
+    assert!(game.decide().is_none());
+
+    let round = reaction_round(&game);
+    assert_eq!(round.phase, RoundPhase::Won { slot: 2 });
+    let reaction = |slot, reaction_us, server_timed| Reaction {
+        slot,
+        reaction_us,
+        server_timed,
+    };
+    assert_eq!(
+        round.reactions,
+        [reaction(2, 300_000, true), reaction(3, 400_000, false)]
+    );
+    assert_eq!(round.false_starts, [reaction(1, -500_000, true)]);
+}
+
+#[test]
+fn quiz_disqualifies_the_winner_when_continuing() {
+    let mut game = Game::default();
+    assert!(game.command(GameCommand::ContinueQuiz, START_US).is_none());
+    game.command(GameCommand::StartQuiz, START_US);
+    game.press(&press(4, 800_000), START_US);
+    game.press(&press(5, 900_000), START_US);
+    game.decide();
+
+    let commands = game.command(GameCommand::ContinueQuiz, START_US).unwrap();
+    assert!(commands.iter().any(|command| matches!(
+        command,
+        UiCommand::Lock(lock) if lock.mask == 1 << 4 && lock.mode == LockMode::Discard
+    )));
+    assert!(commands
+        .iter()
+        .any(|command| matches!(command, UiCommand::Arm(mask) if *mask == !(1 << 4))));
+    // Disqualified players are ignored in later rounds.
+    assert!(!game.press(&press(4, 100_000), START_US));
+    game.press(&press(5, 200_000), START_US);
+    game.decide();
+
+    let GameState::Quiz(quiz) = game.state() else {
+        panic!("no quiz: {:?}", game.state());
+    };
+    assert_eq!(quiz.disqualified, [4]);
+    assert_eq!(quiz.round.number, 2);
+    assert_eq!(quiz.round.phase, RoundPhase::Won { slot: 5 });
+}
+
+#[test]
+fn quiz_turns_off_the_local_winners_of_the_other_boards() {
+    let first = SlotRange { first: 0, len: 7 };
+    let second = SlotRange { first: 7, len: 7 };
+    let mut game = Game::default();
+    game.command(GameCommand::StartQuiz, START_US);
+    // Both boards lit their own first press, slot 9 was the later one.
+    assert!(game.press(&press(2, 800_000), START_US));
+    assert!(game.press(&press(9, 900_000), START_US));
+    let commands = game.decide().unwrap();
+
+    let to_board = |range| {
+        commands
+            .iter()
+            .filter_map(|command| command.to_board(range))
+            .collect::<Vec<_>>()
+    };
+    assert!(matches!(
+        to_board(&first)[..],
+        [ServerToBoard::LedFrame(LedFrame { mask: 0b111_1011, on: 0 }), ServerToBoard::LedEffect(effect)]
+            if effect.mask == 1 << 2
+    ));
+    assert_eq!(
+        to_board(&second),
+        [ServerToBoard::LedFrame(LedFrame {
+            mask: 0b111_1111,
+            on: 0
+        })]
+    );
+}
+
+#[test]
+fn faster_press_arriving_later_wins() {
+    let mut game = Game::default();
+    game.command(GameCommand::StartReaction, START_US);
+    game.go();
+    let round = reaction_round(&game);
+
+    // The press of the second board took longer to arrive.
+    let arrival_us = round.go_us + 400_000;
+    assert!(game.press(&timed_press(9, &round, 300_000), arrival_us));
+    assert!(game.press(&timed_press(2, &round, 250_000), arrival_us + 10_000));
+    assert_eq!(reaction_round(&game).phase, RoundPhase::Settling);
+
+    game.decide().unwrap();
+    assert_eq!(reaction_round(&game).phase, RoundPhase::Won { slot: 2 });
+}
+
+#[test]
+fn presses_within_a_board_clock_tick_are_won_by_the_first_to_arrive() {
+    let mut game = Game::default();
+    game.command(GameCommand::StartReaction, START_US);
+    game.go();
+    let round = reaction_round(&game);
+
+    let arrival_us = round.go_us + 400_000;
+    assert!(game.press(&timed_press(9, &round, 300_000), arrival_us));
+    assert!(game.press(&timed_press(2, &round, 299_980), arrival_us + 10_000));
+
+    game.decide().unwrap();
+    assert_eq!(reaction_round(&game).phase, RoundPhase::Won { slot: 9 });
+}
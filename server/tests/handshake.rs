@@ -0,0 +1,37 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use common::{encode_frame, MAX_FRAME_SIZE};
+use server::{
+    config::ChannelConfig, net_sockets::serve_board, players::PlayerRegistry, LinkConfig,
+    UiBackendRouterInner,
+};
+use tokio::{io::AsyncWriteExt, time::timeout};
+
+#[tokio::test]
+async fn hello_of_another_version_is_rejected_without_waiting() {
+    let uib_router = Arc::new(UiBackendRouterInner::new(
+        LinkConfig::default(),
+        ChannelConfig::default(),
+        None,
+        None,
+        PlayerRegistry::default(),
+        String::new(),
+    ));
+    let (mut board, server) = tokio::io::duplex(4096);
+    let (reader, writer) = tokio::io::split(server);
+    let addr = SocketAddr::from(([127, 0, 0, 1], 40000));
+    let session = tokio::spawn(serve_board(reader, writer, addr, uib_router.clone()));
+
+    // Hello of an old firmware which only consists of variant and protocol version.
+    let mut frame_buf = [0u8; MAX_FRAME_SIZE];
+    let frame = encode_frame(&(0u8, 0u16), &mut frame_buf).unwrap();
+    board.write_all(frame).await.unwrap();
+
+    // The connection stays open, so only the version check ends the session before the Hello
+    // timeout.
+    timeout(Duration::from_millis(500), session)
+        .await
+        .expect("board was not rejected")
+        .unwrap();
+    assert_eq!(uib_router.player_slots.lock().unwrap().ranges().count(), 0);
+}
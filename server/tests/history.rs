@@ -0,0 +1,114 @@
+use std::{
+    fs, thread,
+    time::{Duration, Instant},
+};
+
+use common::ButtonPress;
+use server::{
+    game::{Game, GameCommand, GameMode, GameState, RoundPhase},
+    history::{History, LeaderboardEntry, Score},
+    websocket::UiButtonPress,
+};
+
+fn press(slot: u8, micros_since_init: u64) -> UiButtonPress {
+    UiButtonPress {
+        press: ButtonPress {
+            button_id: slot,
+            board_time_us: 0,
+            micros_since_init,
+            before_game: false,
+            locked: false,
+            first_after_arm: false,
+            seq: 1,
+        },
+        board_id: 1,
+        slot,
+        server_time: None,
+    }
+}
+
+struct Recorder {
+    game: Game,
+    history: History,
+}
+
+impl Recorder {
+    fn command(&mut self, command: GameCommand) {
+        let before = self.game.state().clone();
+        self.game.command(command, 0).unwrap();
+        self.history.command(command, &before, self.game.state());
+    }
+
+    fn press(&mut self, press: UiButtonPress, player: &str) {
+        assert!(self.game.press(&press, 0));
+        self.history.press(&press, self.game.state(), player.into());
+    }
+
+    fn decide(&mut self) {
+        self.game.decide().unwrap();
+        let GameState::Quiz(quiz) = self.game.state() else {
+            panic!("no quiz: {:?}", self.game.state());
+        };
+        let RoundPhase::Won { slot } = quiz.round.phase else {
+            panic!("no winner: {:?}", quiz.round);
+        };
+        self.history.winner(slot);
+    }
+}
+
+#[test]
+fn quiz_winners_score_unless_disqualified() {
+    let path = std::env::temp_dir().join(format!("server-history-{}.sqlite", std::process::id()));
+    fs::remove_file(&path).ok();
+    let mut recorder = Recorder {
+        game: Game::default(),
+        history: History::open(&path).unwrap(),
+    };
+
+    recorder.command(GameCommand::StartQuiz);
+    recorder.press(press(0, 300_000), "Red");
+    recorder.press(press(1, 400_000), "Blue");
+    recorder.decide();
+    recorder.command(GameCommand::ContinueQuiz);
+    recorder.press(press(1, 200_000), "Blue");
+    recorder.decide();
+    // A new quiz is a new session.
+    recorder.command(GameCommand::StartQuiz);
+    recorder.press(press(0, 100_000), "Red");
+    recorder.decide();
+
+    // The history is written in the background, the last press completes the leaderboard.
+    let entry = |player: &str, points, sessions| LeaderboardEntry {
+        player: player.into(),
+        points,
+        sessions,
+    };
+    let expected = [entry("Blue", 1, 1), entry("Red", 1, 2)];
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while recorder.history.leaderboard(Some(GameMode::Quiz)).unwrap() != expected {
+        assert!(Instant::now() < deadline, "history not written");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let sessions = recorder.history.sessions().unwrap();
+    assert_eq!(sessions.len(), 2);
+    let first = recorder.history.session(sessions[1].id).unwrap().unwrap();
+    assert_eq!(first.session.mode, GameMode::Quiz);
+    assert!(first.session.ended_us.is_some());
+    assert_eq!(first.rounds.len(), 2);
+    let presses = &first.rounds[0].presses;
+    assert_eq!(presses.len(), 2);
+    assert!(presses[0].winner && presses[0].disqualified);
+    assert_eq!(presses[1].reaction_us, 400_000);
+    let score = |player: &str, points| Score {
+        player: player.into(),
+        points,
+    };
+    assert_eq!(first.scores, [score("Blue", 1), score("Red", 0)]);
+    assert!(recorder
+        .history
+        .leaderboard(Some(GameMode::Reaction))
+        .unwrap()
+        .is_empty());
+    fs::remove_file(&path).ok();
+}
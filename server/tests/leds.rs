@@ -0,0 +1,119 @@
+use common::{
+    features, EffectKind, FirmwareVersion, Hello, LedEffect, LedFrame, LedUpdate, ServerToBoard,
+};
+use server::{
+    net_sockets::{adapt_to_board, merge_led_updates, LED_MERGE_WINDOW},
+    slots::SlotRange,
+    websocket::{UiCommand, UiLedUpdate},
+};
+use tokio::sync::broadcast;
+
+const SLOTS: SlotRange = SlotRange { first: 0, len: 7 };
+
+fn led(slot: u8, on: bool) -> UiCommand {
+    UiCommand::LedUpdate(UiLedUpdate { slot, on })
+}
+
+fn hello(features: u32) -> Hello {
+    Hello {
+        protocol_version: 0,
+        firmware_version: FirmwareVersion {
+            major: 0,
+            minor: 0,
+            patch: 0,
+        },
+        board_id: 1,
+        num_buttons: 7,
+        num_leds: 6,
+        features,
+        boot_id: 0,
+    }
+}
+
+#[tokio::test]
+async fn burst_of_updates_is_merged_until_another_command() {
+    let (tx, mut rx) = broadcast::channel(16);
+    tx.send(led(1, true)).unwrap();
+    tx.send(led(1, false)).unwrap();
+    tx.send(led(2, true)).unwrap();
+    tx.send(UiCommand::InitReactionGame(0)).unwrap();
+    tx.send(led(3, true)).unwrap();
+
+    let first = ServerToBoard::LedUpdate(LedUpdate {
+        button_id: 0,
+        on: true,
+    });
+    let msgs = merge_led_updates(first, &mut rx, &SLOTS).await;
+    assert_eq!(
+        msgs,
+        [
+            ServerToBoard::LedFrame(LedFrame {
+                mask: 0b111,
+                on: 0b101
+            }),
+            ServerToBoard::InitReactionGame(0),
+        ]
+    );
+    // Commands after the end of the burst are left for the connection loop.
+    assert!(matches!(rx.try_recv(), Ok(UiCommand::LedUpdate(_))));
+}
+
+#[tokio::test]
+async fn updates_after_the_window_are_not_merged() {
+    let (tx, mut rx) = broadcast::channel(16);
+    let late = tokio::spawn(async move {
+        tokio::time::sleep(LED_MERGE_WINDOW * 10).await;
+        tx.send(led(1, true)).unwrap();
+        tx
+    });
+
+    let first = ServerToBoard::LedFrame(LedFrame { mask: 1, on: 1 });
+    let msgs = merge_led_updates(first.clone(), &mut rx, &SLOTS).await;
+    assert_eq!(msgs, [first]);
+    let _tx = late.await.unwrap();
+    assert!(matches!(rx.try_recv(), Ok(UiCommand::LedUpdate(_))));
+}
+
+#[test]
+fn effects_degrade_on_boards_without_effects() {
+    let flash = ServerToBoard::LedEffect(LedEffect {
+        mask: 0b1_0000_0100,
+        kind: EffectKind::FlashThenHold {
+            period_ms: 200,
+            count: 3,
+        },
+    });
+    let blink = ServerToBoard::LedEffect(LedEffect {
+        mask: 0b100,
+        kind: EffectKind::Blink {
+            period_ms: 200,
+            count: 3,
+        },
+    });
+
+    // Only the LEDs the board has are kept.
+    let with_effects = hello(features::LED_EFFECTS | features::LED_FRAME);
+    assert!(matches!(
+        adapt_to_board(&with_effects, flash.clone())[..],
+        [ServerToBoard::LedEffect(LedEffect { mask: 0b100, .. })]
+    ));
+    // Without effects, the final state of a flash is shown and a blink is dropped.
+    let frames = hello(features::LED_FRAME);
+    assert_eq!(
+        adapt_to_board(&frames, flash.clone()),
+        [ServerToBoard::LedFrame(LedFrame {
+            mask: 0b100,
+            on: 0b100
+        })]
+    );
+    assert!(adapt_to_board(&frames, blink).is_empty());
+    let updates = hello(features::LED_UPDATE);
+    assert_eq!(
+        adapt_to_board(&updates, flash.clone()),
+        [ServerToBoard::LedUpdate(LedUpdate {
+            button_id: 2,
+            on: true
+        })]
+    );
+    assert!(adapt_to_board(&hello(0), flash).is_empty());
+}
@@ -0,0 +1,74 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use common::{
+    encode_frame, BoardToServer, FirmwareVersion, Hello, LinkTiming, MAX_FRAME_SIZE,
+    PROTOCOL_VERSION,
+};
+use server::{
+    config::ChannelConfig,
+    net_sockets::serve_board,
+    players::PlayerRegistry,
+    websocket::{DisconnectReason, UiEvent},
+    UiBackendRouterInner,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    time::{timeout, Instant},
+};
+
+const BOARD_ID: u32 = 1000;
+
+#[tokio::test]
+async fn silent_board_is_disconnected_after_the_timeout() {
+    let link = LinkTiming {
+        heartbeat_ms: 50,
+        timeout_ms: 200,
+    };
+    let uib_router = Arc::new(UiBackendRouterInner::new(
+        link.into(),
+        ChannelConfig::default(),
+        None,
+        None,
+        PlayerRegistry::default(),
+        String::new(),
+    ));
+    let mut ui_rx = uib_router.frontend_tx.subscribe();
+    let (mut board, server) = tokio::io::duplex(4096);
+    let (reader, writer) = tokio::io::split(server);
+    let addr = SocketAddr::from(([127, 0, 0, 1], 40000));
+    let session = tokio::spawn(serve_board(reader, writer, addr, uib_router.clone()));
+
+    let hello = BoardToServer::Hello(Hello {
+        protocol_version: PROTOCOL_VERSION,
+        firmware_version: FirmwareVersion {
+            major: 0,
+            minor: 0,
+            patch: 0,
+        },
+        board_id: BOARD_ID,
+        num_buttons: 7,
+        num_leds: 6,
+        features: 0,
+        boot_id: 1,
+    });
+    let mut frame_buf = [0u8; MAX_FRAME_SIZE];
+    let frame = encode_frame(&hello, &mut frame_buf).unwrap();
+    board.write_all(frame).await.unwrap();
+    let connected = Instant::now();
+
+    // The board stays silent but keeps the connection open.
+    timeout(Duration::from_secs(2), session)
+        .await
+        .expect("session did not end")
+        .unwrap();
+    assert!(connected.elapsed() >= Duration::from_millis(link.timeout_ms.into()));
+
+    let mut disconnected = None;
+    while let Ok(event) = ui_rx.try_recv() {
+        if let UiEvent::BoardDisconnected { board_id, reason } = event {
+            disconnected = Some((board_id, reason));
+        }
+    }
+    assert_eq!(disconnected, Some((BOARD_ID, DisconnectReason::Timeout)));
+    drop(board);
+}
@@ -0,0 +1,44 @@
+use server::{
+    players::{Player, PlayerRegistry},
+    slots::PlayerSlots,
+};
+
+fn player(board_id: Option<u32>, button_id: u8, name: &str) -> Player {
+    Player {
+        board_id,
+        button_id,
+        name: name.into(),
+        color: None,
+        sound: None,
+    }
+}
+
+#[test]
+fn board_specific_players_take_precedence() {
+    let mut registry = PlayerRegistry::default();
+    registry
+        .set(vec![
+            player(None, 0, "Red"),
+            player(Some(2), 0, "Blue"),
+            player(None, 1, "Green"),
+        ])
+        .unwrap();
+    let mut slots = PlayerSlots::default();
+    slots.assign(1, 2).unwrap();
+    slots.assign(2, 2).unwrap();
+
+    let names: Vec<_> = registry
+        .by_slot(&slots)
+        .into_iter()
+        .map(|player| (player.slot, player.board_id, player.player.name))
+        .collect();
+    assert_eq!(
+        names,
+        [
+            (0, 1, "Red".to_string()),
+            (1, 1, "Green".to_string()),
+            (2, 2, "Blue".to_string()),
+            (3, 2, "Green".to_string()),
+        ]
+    );
+}
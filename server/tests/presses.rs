@@ -0,0 +1,89 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use common::{
+    encode_frame, BoardToServer, ButtonPress, FirmwareVersion, Hello, MAX_FRAME_SIZE,
+    PROTOCOL_VERSION,
+};
+use server::{
+    config::ChannelConfig, net_sockets::serve_board, players::PlayerRegistry, websocket::UiEvent,
+    LinkConfig, UiBackendRouter, UiBackendRouterInner,
+};
+use tokio::io::AsyncWriteExt;
+
+const BOARD_ID: u32 = 1000;
+
+/// Connect a board, send presses with the sequence numbers and close the connection.
+async fn connect(uib_router: &UiBackendRouter, boot_id: u32, seqs: &[u32]) {
+    let (board, server) = tokio::io::duplex(4096);
+    let (reader, writer) = tokio::io::split(server);
+    let addr = SocketAddr::from(([127, 0, 0, 1], 40000));
+    let session = tokio::spawn(serve_board(reader, writer, addr, uib_router.clone()));
+
+    let hello = BoardToServer::Hello(Hello {
+        protocol_version: PROTOCOL_VERSION,
+        firmware_version: FirmwareVersion {
+            major: 0,
+            minor: 0,
+            patch: 0,
+        },
+        board_id: BOARD_ID,
+        num_buttons: 7,
+        num_leds: 6,
+        features: 0,
+        boot_id,
+    });
+    let presses = seqs.iter().map(|&seq| {
+        BoardToServer::ButtonPress(ButtonPress {
+            button_id: 2,
+            board_time_us: seq as u64 * 1000,
+            micros_since_init: 0,
+            before_game: true,
+            locked: false,
+            first_after_arm: false,
+            seq,
+        })
+    });
+
+    let (_board_reader, mut board_writer) = tokio::io::split(board);
+    let mut frame_buf = [0u8; MAX_FRAME_SIZE];
+    for msg in [hello].into_iter().chain(presses) {
+        let frame = encode_frame(&msg, &mut frame_buf).unwrap();
+        board_writer.write_all(frame).await.unwrap();
+    }
+    board_writer.shutdown().await.unwrap();
+    session.await.unwrap();
+}
+
+fn delivered_seqs(ui_rx: &mut tokio::sync::broadcast::Receiver<UiEvent>) -> Vec<u32> {
+    let mut seqs = Vec::new();
+    while let Ok(event) = ui_rx.try_recv() {
+        if let UiEvent::ButtonPress(press) = event {
+            seqs.push(press.press.seq);
+        }
+    }
+    seqs
+}
+
+#[tokio::test]
+async fn retransmitted_presses_are_delivered_once_per_boot() {
+    let uib_router = Arc::new(UiBackendRouterInner::new(
+        LinkConfig::default(),
+        ChannelConfig::default(),
+        None,
+        None,
+        PlayerRegistry::default(),
+        String::new(),
+    ));
+    let mut ui_rx = uib_router.frontend_tx.subscribe();
+
+    connect(&uib_router, 1, &[1, 2]).await;
+    assert_eq!(delivered_seqs(&mut ui_rx), [1, 2]);
+
+    // The acknowledgement of 2 was lost, so the board retransmits it after reconnecting.
+    connect(&uib_router, 1, &[2, 3]).await;
+    assert_eq!(delivered_seqs(&mut ui_rx), [3]);
+
+    // After a reboot, the sequence numbers start again.
+    connect(&uib_router, 2, &[1]).await;
+    assert_eq!(delivered_seqs(&mut ui_rx), [1]);
+}
@@ -0,0 +1,117 @@
+use std::{io, net::SocketAddr, path::Path, sync::Arc};
+
+use common::{BoardToServer, ButtonPress, FirmwareVersion, Hello, PROTOCOL_VERSION};
+use server::{
+    capture::{read_capture, replay, CaptureRecord, CapturedMessage},
+    config::ChannelConfig,
+    players::PlayerRegistry,
+    websocket::UiEvent,
+    LinkConfig, UiBackendRouter, UiBackendRouterInner,
+};
+use tokio::sync::broadcast::Receiver;
+
+fn router() -> UiBackendRouter {
+    Arc::new(UiBackendRouterInner::new(
+        LinkConfig::default(),
+        ChannelConfig::default(),
+        None,
+        None,
+        PlayerRegistry::default(),
+        String::new(),
+    ))
+}
+
+fn delivered_presses(ui_rx: &mut Receiver<UiEvent>) -> Vec<(u32, u8, u64)> {
+    let mut presses = Vec::new();
+    while let Ok(event) = ui_rx.try_recv() {
+        if let UiEvent::ButtonPress(press) = event {
+            presses.push((press.board_id, press.slot, press.press.micros_since_init));
+        }
+    }
+    presses
+}
+
+/// Hello of board `board_id` recorded at `time_ms`, and a press 100 ms later with
+/// `micros_since_init`, which also serves as its sequence number.
+fn connection(
+    time_ms: u64,
+    port: u16,
+    board_id: u32,
+    micros_since_init: u64,
+) -> [CaptureRecord; 2] {
+    let addr = SocketAddr::from(([192, 168, 100, 1], port));
+    let hello = BoardToServer::Hello(Hello {
+        protocol_version: PROTOCOL_VERSION,
+        firmware_version: FirmwareVersion {
+            major: 0,
+            minor: 0,
+            patch: 0,
+        },
+        board_id,
+        num_buttons: 7,
+        num_leds: 6,
+        features: 0,
+        boot_id: 1,
+    });
+    let press = BoardToServer::ButtonPress(ButtonPress {
+        button_id: 0,
+        board_time_us: 0,
+        micros_since_init,
+        before_game: false,
+        locked: false,
+        first_after_arm: false,
+        seq: micros_since_init as u32,
+    });
+    [(0, hello), (100, press)].map(|(offset_ms, msg)| CaptureRecord {
+        time_us: (time_ms + offset_ms) * 1000,
+        board_id,
+        addr,
+        msg: CapturedMessage::FromBoard(msg),
+    })
+}
+
+#[tokio::test]
+async fn replayed_capture_reproduces_the_presses() {
+    let records = read_capture(Path::new("tests/captures/two_boards.jsonl")).unwrap();
+    let uib_router = router();
+    let mut ui_rx = uib_router.frontend_tx.subscribe();
+
+    replay(records, 100.0, uib_router).await;
+
+    assert_eq!(
+        delivered_presses(&mut ui_rx),
+        [(1000, 8, 351_461), (1001, 4, 452_841)]
+    );
+}
+
+#[tokio::test]
+async fn late_and_reconnecting_boards_are_replayed() {
+    // Both connect later than the server waits for a Hello, counted from the replay start.
+    let records = [
+        connection(0, 40000, 1000, 1),
+        connection(2500, 40001, 1001, 2),
+        connection(2600, 40002, 1000, 3),
+    ]
+    .concat();
+    let uib_router = router();
+    let mut ui_rx = uib_router.frontend_tx.subscribe();
+
+    replay(records, 1.0, uib_router).await;
+
+    assert_eq!(
+        delivered_presses(&mut ui_rx),
+        [(1000, 0, 1), (1001, 7, 2), (1000, 0, 3)]
+    );
+}
+
+#[test]
+fn capture_of_another_protocol_version_is_rejected() {
+    let error = read_capture(Path::new("tests/captures/old_version.jsonl")).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "line 1: board 1001 speaks protocol version 0, this server version {PROTOCOL_VERSION}"
+        )
+    );
+}
@@ -0,0 +1,137 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+    http::{HeaderMap, StatusCode},
+    routing::get,
+    Extension, Json, Router,
+};
+use futures_util::SinkExt;
+use server::{
+    api::{set_players, HOST_PIN_HEADER},
+    config::ChannelConfig,
+    game::{GameCommand, GameState},
+    host_pin::{HostPin, PinError, MAX_FAILED_ATTEMPTS},
+    players::PlayerRegistry,
+    websocket::{ws_handler, DisconnectReason, Role, UiCommand, UiEvent},
+    LinkConfig, UiBackendRouter, UiBackendRouterInner,
+};
+use tokio::{sync::broadcast::error::TryRecvError, time::timeout};
+use tokio_tungstenite::{connect_async, tungstenite::Error, tungstenite::Message};
+
+fn router(host_pin: &str) -> UiBackendRouter {
+    Arc::new(UiBackendRouterInner::new(
+        LinkConfig::default(),
+        ChannelConfig::default(),
+        None,
+        None,
+        PlayerRegistry::default(),
+        host_pin.into(),
+    ))
+}
+
+/// Serve the websocket on a free local port, returning its URL without the query.
+fn serve_websocket(uib_router: UiBackendRouter) -> String {
+    let app = Router::new()
+        .route("/ws", get(ws_handler))
+        .layer(Extension(uib_router));
+    let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>());
+    let url = format!("ws://{}/ws", server.local_addr());
+    tokio::spawn(server);
+    url
+}
+
+#[test]
+fn roles_see_their_own_view() {
+    let countdown = UiEvent::Countdown {
+        round: 1,
+        countdown_ms: 3000,
+    };
+    let disconnected = UiEvent::BoardDisconnected {
+        board_id: 1,
+        reason: DisconnectReason::Timeout,
+    };
+    let game = UiEvent::GameState(GameState::Idle);
+
+    assert!(Role::Host.sees(&countdown));
+    assert!(!Role::Display.sees(&countdown));
+    assert!(!Role::Spectator.sees(&countdown));
+    assert!(Role::Display.sees(&disconnected));
+    assert!(!Role::Spectator.sees(&disconnected));
+    assert!(Role::Spectator.sees(&game));
+}
+
+#[tokio::test]
+async fn only_the_host_changes_the_players() {
+    let uib_router = router("1234");
+    let with_pin = |pin: &str| {
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST_PIN_HEADER, pin.parse().unwrap());
+        headers
+    };
+
+    for headers in [HeaderMap::new(), with_pin("4321")] {
+        let result = set_players(Extension(uib_router.clone()), headers, Json(Vec::new())).await;
+        assert_eq!(result.unwrap_err().0, StatusCode::UNAUTHORIZED);
+    }
+    assert!(!uib_router.players.lock().unwrap().players().is_empty());
+
+    let result = set_players(
+        Extension(uib_router.clone()),
+        with_pin("1234"),
+        Json(Vec::new()),
+    )
+    .await;
+    assert!(result.is_ok());
+    assert!(uib_router.players.lock().unwrap().players().is_empty());
+}
+
+#[tokio::test]
+async fn host_websocket_needs_the_pin() {
+    let url = serve_websocket(router("1234"));
+
+    for query in ["role=host", "role=host&pin=4321"] {
+        match connect_async(format!("{url}?{query}")).await {
+            Err(Error::Http(response)) => assert_eq!(response.status(), StatusCode::UNAUTHORIZED),
+            result => panic!("host connected with {query}: {result:?}"),
+        }
+    }
+    connect_async(format!("{url}?role=host&pin=1234"))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn commands_of_displays_are_ignored() {
+    let uib_router = router("1234");
+    let mut board_rx = uib_router.board_tx.subscribe();
+    let url = serve_websocket(uib_router);
+
+    let (mut display, _) = connect_async(format!("{url}?role=display")).await.unwrap();
+    let (mut host, _) = connect_async(format!("{url}?role=host&pin=1234"))
+        .await
+        .unwrap();
+    let command = |command: &str| Message::Text(format!(r#"{{"Game": "{command}"}}"#));
+    display.send(command("StartQuiz")).await.unwrap();
+    host.send(command("StartReaction")).await.unwrap();
+
+    let received = timeout(Duration::from_secs(5), board_rx.recv()).await;
+    assert!(matches!(
+        received,
+        Ok(Ok(UiCommand::Game(GameCommand::StartReaction)))
+    ));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(matches!(board_rx.try_recv(), Err(TryRecvError::Empty)));
+}
+
+#[test]
+fn host_pin_locks_out_after_wrong_attempts() {
+    let host_pin = HostPin::new("1234".into());
+    assert_eq!(host_pin.check(Some("1234")), Ok(()));
+    for _ in 0..MAX_FAILED_ATTEMPTS {
+        assert_eq!(host_pin.check(Some("12345")), Err(PinError::Wrong));
+    }
+    // Even the right PIN is rejected until the lockout passed.
+    assert_eq!(host_pin.check(Some("1234")), Err(PinError::LockedOut));
+    assert_eq!(host_pin.check(None), Err(PinError::LockedOut));
+}
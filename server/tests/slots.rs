@@ -0,0 +1,38 @@
+use common::{LedFrame, ServerToBoard};
+use server::{
+    slots::{PlayerSlots, SlotRange},
+    websocket::{UiCommand, UiLedFrame},
+};
+
+#[test]
+fn boards_keep_their_slots_across_reconnects() {
+    let mut slots = PlayerSlots::default();
+    let first = slots.assign(1, 7).unwrap();
+    let second = slots.assign(2, 7).unwrap();
+    assert_eq!(first, SlotRange { first: 0, len: 7 });
+    assert_eq!(second, SlotRange { first: 7, len: 7 });
+    assert_eq!(slots.assign(1, 7), Some(first));
+
+    assert_eq!(second.slot(3), Some(10));
+    assert_eq!(second.button(10), Some(3));
+    assert_eq!(second.button(3), None);
+}
+
+#[test]
+fn commands_are_routed_to_the_owning_board() {
+    let first = SlotRange { first: 0, len: 7 };
+    let second = SlotRange { first: 7, len: 7 };
+    let command = UiCommand::LedFrame(UiLedFrame {
+        mask: 0b11 << 8,
+        on: 0b01 << 8,
+    });
+
+    assert_eq!(command.to_board(&first), None);
+    assert_eq!(
+        command.to_board(&second),
+        Some(ServerToBoard::LedFrame(LedFrame {
+            mask: 0b110,
+            on: 0b010,
+        }))
+    );
+}
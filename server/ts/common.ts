@@ -1,6 +1,89 @@
-export interface ButtonPress {
+export interface Reaction {
+    // Global player slot, unique across all boards.
+    slot: number;
+    // Time relative to the end of the countdown, not positive for false starts.
+    reaction_us: number;
+    server_timed: boolean;
+}
+
+export interface Round {
+    number: number;
+    reactions: Reaction[];
+    false_starts: Reaction[];
+    phase: "Countdown" | "Open" | "Settling" | { Won: { slot: number } };
+}
+
+export interface Quiz {
+    round: Round;
+    disqualified: number[];
+}
+
+export type GameState = "Idle" | { Reaction: Round } | { Quiz: Quiz };
+
+export interface Player {
+    slot: number;
+    board_id: number;
     button_id: number;
-    millis_since_init: number;
+    name: string;
+    color?: string;
+    sound?: string;
+}
+
+// Players by slot, as last sent by the server.
+var players: { [slot: number]: Player } = {};
+
+export function playerName(slot: number): string {
+    return players[slot]?.name ?? 'Unknown';
+}
+
+export function playerRow(slot: number, time?: number): HTMLTableRowElement {
+    const row = createTableRow(playerName(slot), time);
+    const color = players[slot]?.color;
+    if (color != null) {
+        row.style.color = color;
+    }
+    return row;
+}
+
+export interface GameEvent {
+    GameState?: GameState;
+    // The countdown of the reaction round ended.
+    Go?: { round: number };
+}
+
+// Parse a server event, logging connection changes of boards and keeping the players.
+export function parseEvent(msg: MessageEvent<any>): GameEvent {
+    const event = JSON.parse(msg.data);
+    if (event.Players) {
+        players = {};
+        for (const player of event.Players as Player[]) {
+            players[player.slot] = player;
+        }
+    }
+    if (event.BoardConnected) {
+        console.log("Board connected:", event.BoardConnected.board_id);
+    } else if (event.BoardDisconnected) {
+        console.warn("Board disconnected:", event.BoardDisconnected.board_id, event.BoardDisconnected.reason);
+    }
+    return event;
+}
+
+// Pages opened with `?pin=<host PIN>` control the game, all others only display it.
+export function connectBackend(): WebSocket {
+    const pin = new URLSearchParams(location.search).get("pin");
+    if (pin == null) {
+        const hostOnly = document.getElementsByClassName("host-only");
+        for (let i = 0; i < hostOnly.length; i++) {
+            (hostOnly[i] as HTMLElement).style.display = "none";
+        }
+        return new WebSocket(`ws://${location.host}/ws?role=display`);
+    }
+    return new WebSocket(`ws://${location.host}/ws?role=host&pin=${encodeURIComponent(pin)}`);
+}
+
+// The server runs the game, the frontends only send commands and render its state.
+export function sendGameCommand(backend: WebSocket, command: object | string) {
+    backend.send(JSON.stringify({ Game: command }));
 }
 
 export function clearTable(table: HTMLTableElement) {
@@ -23,8 +106,6 @@ export function createTableRow(name: string, time?: number): HTMLTableRowElement
     return newRow;
 }
 
-
-
 export function playAudio(audioElement: HTMLAudioElement) {
   if (audioElement.paused) {
     audioElement.play();
@@ -33,10 +114,66 @@ export function playAudio(audioElement: HTMLAudioElement) {
   }
 }
 
-export function setAllButtons(backend: WebSocket, on: boolean) {
-  for (let i = 0; i < 6; i++) {
-    backend.send(
-      `{"LedUpdate": {"button_id": ${i}, "on": ${on}}}`
-    )
+var audioElements: { [sound: string]: HTMLAudioElement } = {};
+
+// Play a sound from `assets/audio`, loading it on first use.
+export function playSound(sound: string) {
+  let audioElement = audioElements[sound];
+  if (audioElement == null) {
+    audioElement = new Audio(`/assets/audio/${sound}.mp3`);
+    audioElements[sound] = audioElement;
+  }
+  playAudio(audioElement);
+}
+
+// Renders the tables of a round, appending the rows which are new since the last state.
+export class RoundView {
+  private number = -1;
+  private reactions = 0;
+  private falseStarts = 0;
+  // Sounds are only played for presses which happen while the page is open.
+  private live = false;
+
+  constructor(
+    private leaderTable: HTMLTableElement,
+    private falseStartTable: HTMLTableElement | null,
+  ) {}
+
+  // Called with `null` for states of other games.
+  render(round: Round | null) {
+    if (round != null) {
+      this.renderRound(round);
+    }
+    this.live = true;
+  }
+
+  private renderRound(round: Round) {
+    if (round.number != this.number) {
+      clearTable(this.leaderTable);
+      if (this.falseStartTable != null) {
+        clearTable(this.falseStartTable);
+      }
+      this.number = round.number;
+      this.reactions = 0;
+      this.falseStarts = 0;
+    }
+
+    for (const reaction of round.reactions.slice(this.reactions)) {
+      this.leaderTable.appendChild(playerRow(reaction.slot, reaction.reaction_us / 1000));
+      this.play(players[reaction.slot]?.sound ?? 'icq');
+    }
+    this.reactions = round.reactions.length;
+
+    for (const reaction of round.false_starts.slice(this.falseStarts)) {
+      this.falseStartTable?.appendChild(playerRow(reaction.slot, reaction.reaction_us / 1000));
+      this.play('boowomp');
+    }
+    this.falseStarts = round.false_starts.length;
   }
-}
\ No newline at end of file
+
+  private play(sound: string) {
+    if (this.live) {
+      playSound(sound);
+    }
+  }
+}
@@ -1,19 +0,0 @@
-export const ID_TO_SOUND = [
-    'create',
-    'duck',
-    'icq',
-    'mario',
-    'mgs',
-    'partyblower',
-    'wololo',
-];
-
-export const ID_TO_NAME = [
-    'AoE2 Create',
-    'Duck',
-    'ICQ',
-    'Mario',
-    'MGS',
-    'Partyblower',
-    'Wololo',
-];
\ No newline at end of file